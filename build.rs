@@ -312,6 +312,20 @@ fn variant_nullable(ty_name: &str) -> TokenStream {
     }
 }
 
+fn type_name_nullable(ty_name: &str) -> TokenStream {
+    let pascal_name = pascal_case(ty_name);
+    let variant = syn::Ident::new(&*pascal_name, Span::call_site());
+    let nullable_variant = syn::Ident::new(&*format!("Nullable{pascal_name}"), Span::call_site());
+    let array_variant = syn::Ident::new(&*format!("Array{pascal_name}"), Span::call_site());
+    let nullable_discriminator = format!("{ty_name}?");
+    let array_discriminator = format!("{ty_name}[]");
+    quote! {
+        Member::#variant(_) => #ty_name,
+        Member::#nullable_variant(_) => #nullable_discriminator,
+        Member::#array_variant(_) => #array_discriminator,
+    }
+}
+
 fn impl_from(ty_name: &str, nullable: bool) -> TokenStream {
     let pascal_name = pascal_case(ty_name);
     let field_ty = syn::Ident::new(&*format!("Field{pascal_name}"), Span::call_site());
@@ -399,10 +413,12 @@ fn main() {
 
     let mut type_stream = TokenStream::new();
     let mut variant_stream = TokenStream::new();
+    let mut type_name_stream = TokenStream::new();
     let mut impl_stream = TokenStream::new();
     for &(name, ty) in types.iter() {
         type_stream.extend(field(name, ty));
         variant_stream.extend(variant_nullable(name));
+        type_name_stream.extend(type_name_nullable(name));
         impl_stream.extend(impl_from(name, true));
         if vector_types.contains(&name) {
             type_stream.extend(vector(name, ty));
@@ -411,6 +427,7 @@ fn main() {
                 let ty_dim = pascal_case(&name_dim);
                 type_stream.extend(field(&name_dim, &ty_dim));
                 variant_stream.extend(variant_nullable(&name_dim));
+                type_name_stream.extend(type_name_nullable(&name_dim));
                 impl_stream.extend(impl_from(&name_dim, true));
             }
         }
@@ -420,6 +437,7 @@ fn main() {
             let ty_name = pascal_case(&quaternion_name);
             type_stream.extend(field(&quaternion_name,&ty_name));
             variant_stream.extend(variant_nullable(&quaternion_name));
+            type_name_stream.extend(type_name_nullable(&quaternion_name));
             impl_stream.extend(impl_from(&quaternion_name, true));
             type_stream.extend(matrix(name, ty));
             for dim in 2..=4 {
@@ -427,6 +445,7 @@ fn main() {
                 let ty_dim = pascal_case(&name_dim);
                 type_stream.extend(field(&name_dim, &ty_dim));
                 variant_stream.extend(variant_nullable(&name_dim));
+                type_name_stream.extend(type_name_nullable(&name_dim));
                 impl_stream.extend(impl_from(&name_dim, true));
             }
         }
@@ -468,6 +487,12 @@ fn main() {
         #[json(rename = "Uri[]")]
         ArrayUri(ArrayUri),
     });
+    type_name_stream.extend(quote! {
+        Member::String(_) => "string",
+        Member::ArrayString(_) => "string[]",
+        Member::Uri(_) => "Uri",
+        Member::ArrayUri(_) => "Uri[]",
+    });
     impl_stream.extend(impl_from("string", false));
 
     type_stream.extend(quote! {
@@ -481,6 +506,20 @@ fn main() {
             #variant_stream
         }
 
+        impl Member {
+            /// The type discriminator the member is serialized with.
+            pub fn type_name(&self) -> &'static str {
+                match self {
+                    Member::Reference(_) => "reference",
+                    Member::List(_) => "list",
+                    Member::SyncObject(_) => "syncObject",
+                    Member::Enum(_) => "enum",
+                    Member::Empty => "empty",
+                    #type_name_stream
+                }
+            }
+        }
+
         #impl_stream
     });
 
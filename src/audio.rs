@@ -0,0 +1,53 @@
+use std::fmt::Display;
+use crate::messages::ImportAudioClipRawData;
+
+/// Why samples can't be encoded for `ImportAudioClipRawData`.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum AudioError {
+    /// The sample rate or channel count is zero.
+    InvalidFormat { sample_rate: u32, channel_count: u16 },
+    /// The number of samples isn't a multiple of the channel count.
+    IncompleteFrame { sample_count: usize, channel_count: u16 },
+    /// The clip has more samples than the protocol can describe.
+    TooLarge,
+}
+
+impl Display for AudioError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AudioError::InvalidFormat { sample_rate, channel_count } => {
+                write!(f, "invalid format: {sample_rate} Hz with {channel_count} channels")
+            }
+            AudioError::IncompleteFrame { sample_count, channel_count } => {
+                write!(f, "{sample_count} samples can't be split into {channel_count} channels")
+            }
+            AudioError::TooLarge => write!(f, "audio clip is too large"),
+        }
+    }
+}
+
+impl std::error::Error for AudioError {}
+
+/// Builds the message header and binary payload for interleaved PCM samples.
+///
+/// With more than one channel, `samples` holds one sample of every channel per frame, e.g. left and
+/// right for stereo. The message id is left empty so the client can assign one.
+pub fn encode_samples(samples: &[f32], sample_rate: u32, channel_count: u16) -> Result<(ImportAudioClipRawData, Vec<u8>), AudioError> {
+    if sample_rate == 0 || channel_count == 0 {
+        return Err(AudioError::InvalidFormat { sample_rate, channel_count });
+    }
+    if !samples.len().is_multiple_of(channel_count as usize) {
+        return Err(AudioError::IncompleteFrame { sample_count: samples.len(), channel_count });
+    }
+    let header = ImportAudioClipRawData {
+        message_id: String::new(),
+        sample_count: i32::try_from(samples.len() / channel_count as usize).map_err(|_| AudioError::TooLarge)?,
+        sample_rate: i32::try_from(sample_rate).map_err(|_| AudioError::TooLarge)?,
+        channel_count: channel_count as i32,
+    };
+    let mut data = Vec::with_capacity(samples.len() * 4);
+    for sample in samples {
+        data.extend_from_slice(&sample.to_le_bytes());
+    }
+    Ok((header, data))
+}
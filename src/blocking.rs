@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::Message as WsMessage;
+pub use tungstenite::{
+    Bytes,
+    client::IntoClientRequest,
+    protocol::WebSocketConfig,
+};
+use crate::messages::{Message, MessageIdGenerator, PayloadError, Request, SequentialIds};
+use crate::responses::{Response, ServerError};
+
+/// Client that runs the connection on the calling thread, without an async runtime.
+///
+/// Requests can be pipelined: `send` returns the message id right away and `receive` waits for the
+/// response to a specific id, keeping responses to other requests until they are asked for.
+pub struct BlockingClient {
+    socket: tungstenite::WebSocket<MaybeTlsStream<TcpStream>>,
+    ids: Box<dyn MessageIdGenerator>,
+    timeout: Option<Duration>,
+    interceptors: Vec<Box<dyn Interceptor>>,
+    /// Requests that were sent and whose response hasn't been received yet.
+    pending: HashMap<String, Pending>,
+    /// Responses that arrived while waiting for another one.
+    received: HashMap<String, Result<(Response, Option<Bytes>)>>,
+    /// A response that is waiting for its binary frame.
+    awaiting_binary: Option<Response>,
+}
+
+struct Pending {
+    /// Whether the response is followed by a binary frame.
+    expects_binary: bool,
+    sent: Instant,
+}
+
+/// Hooks that run for every request, in the order they were added with `add_interceptor`.
+/// `after_receive` runs in reverse order.
+pub trait Interceptor: Send {
+    /// Called once the message has its id, right before it is sent. An error fails the send instead.
+    fn before_send(&mut self, msg: &mut Message, data: Option<&Bytes>) -> Result<()> {
+        let _ = (msg, data);
+        Ok(())
+    }
+
+    /// Called with the response to a request, `latency` is the time since the message was sent.
+    fn after_receive(&mut self, response: &Response, latency: Duration) {
+        let _ = (response, latency);
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    WebSocket(tungstenite::Error),
+    Closed,
+    Timeout,
+    /// Timeouts are only supported on plain `ws://` connections.
+    UnsupportedTimeout,
+    Json(resoxide_json::Error),
+    /// Another request with the same message id is still waiting for its response.
+    DuplicateMessageId(String),
+    /// No request with this message id is waiting for a response.
+    UnknownMessageId(String),
+    /// The response to this request could not be decoded.
+    Decode { error: resoxide_json::Error, text: String },
+    /// The binary payload doesn't fit the message.
+    InvalidPayload(PayloadError),
+    /// The response should have been followed by a binary frame, but another frame came first.
+    MissingBinary,
+    /// An interceptor refused to send the message.
+    Rejected(String),
+    /// Resonite reported that the request failed.
+    Server(ServerError),
+    /// The response wasn't of the type the request is answered with.
+    UnexpectedResponse(Box<Response>),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        <Self as std::fmt::Debug>::fmt(self, f)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<tungstenite::Error> for Error {
+    fn from(e: tungstenite::Error) -> Self {
+        match e {
+            tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => Self::Closed,
+            tungstenite::Error::Io(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => Self::Timeout,
+            e => Self::WebSocket(e),
+        }
+    }
+}
+
+impl From<ServerError> for Error {
+    fn from(e: ServerError) -> Self {
+        Self::Server(e)
+    }
+}
+
+impl From<PayloadError> for Error {
+    fn from(e: PayloadError) -> Self {
+        Self::InvalidPayload(e)
+    }
+}
+
+impl From<resoxide_json::Error> for Error {
+    fn from(e: resoxide_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl BlockingClient {
+    pub fn connect<R: IntoClientRequest>(request: R) -> Result<BlockingClient> {
+        Self::connect_with_config(request, WebSocketConfig::default())
+    }
+
+    pub fn connect_port(port: u16) -> Result<BlockingClient> {
+        Self::connect(format!("ws://localhost:{}", port))
+    }
+
+    pub fn connect_with_config<R: IntoClientRequest>(request: R, config: WebSocketConfig) -> Result<BlockingClient> {
+        let (socket, _) = tungstenite::client::connect_with_config(request, Some(config), 3)?;
+        Ok(BlockingClient {
+            socket,
+            ids: Box::new(SequentialIds::default()),
+            timeout: None,
+            interceptors: vec![],
+            pending: HashMap::new(),
+            received: HashMap::new(),
+            awaiting_binary: None,
+        })
+    }
+
+    /// Default timeout for `receive` and `call`, measured from the moment they are called. Only
+    /// plain `ws://` connections support timeouts, others fail with `Error::UnsupportedTimeout`.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// How ids are assigned to messages that are sent without one. Ids set by the caller are kept.
+    pub fn set_message_ids<G: MessageIdGenerator + 'static>(&mut self, ids: G) {
+        self.ids = Box::new(ids);
+    }
+
+    /// Adds an interceptor to the chain, see `Interceptor`.
+    pub fn add_interceptor<I: Interceptor + 'static>(&mut self, interceptor: I) {
+        self.interceptors.push(Box::new(interceptor));
+    }
+
+    /// Number of requests that were sent and not received yet.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Sends a message without waiting for the response and returns its message id.
+    pub fn send(&mut self, msg: Message, data: Option<Bytes>) -> Result<String> {
+        self.send_impl(msg, data, false)
+    }
+
+    /// Like `send`, but the response must be followed by a binary frame, see `receive_with_binary`.
+    pub fn send_expecting_binary(&mut self, msg: Message, data: Option<Bytes>) -> Result<String> {
+        self.send_impl(msg, data, true)
+    }
+
+    fn send_impl(&mut self, msg: Message, data: Option<Bytes>, expects_binary: bool) -> Result<String> {
+        let mut msg = if msg.message_id().is_empty() {
+            msg.with_message_id(self.ids.next_id())
+        } else {
+            msg
+        };
+        for interceptor in &mut self.interceptors {
+            interceptor.before_send(&mut msg, data.as_ref())?;
+        }
+        // Checked after the interceptors, which may have changed the message.
+        msg.check_payload_len(data.as_ref().map(Bytes::len))?;
+        let msg_id = msg.message_id().to_string();
+        if self.pending.contains_key(&msg_id) || self.received.contains_key(&msg_id) {
+            return Err(Error::DuplicateMessageId(msg_id));
+        }
+        self.socket.write(WsMessage::text(msg.serialize()?))?;
+        if let Some(data) = data {
+            self.socket.write(WsMessage::Binary(data))?;
+        }
+        self.socket.flush()?;
+        self.pending.insert(msg_id.clone(), Pending { expects_binary, sent: Instant::now() });
+        Ok(msg_id)
+    }
+
+    pub fn receive(&mut self, msg_id: &str) -> Result<Response> {
+        Ok(self.receive_impl(msg_id, self.timeout)?.0)
+    }
+
+    pub fn receive_with_timeout(&mut self, msg_id: &str, timeout: Duration) -> Result<Response> {
+        Ok(self.receive_impl(msg_id, Some(timeout))?.0)
+    }
+
+    /// Like `receive`, but also returns the binary frame that followed the response, if any.
+    pub fn receive_with_binary(&mut self, msg_id: &str) -> Result<(Response, Option<Bytes>)> {
+        self.receive_impl(msg_id, self.timeout)
+    }
+
+    /// Waits for the response to `msg_id`. On timeout the request is forgotten and a late response is discarded.
+    fn receive_impl(&mut self, msg_id: &str, timeout: Option<Duration>) -> Result<(Response, Option<Bytes>)> {
+        // A timeout too long to represent can't expire.
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        loop {
+            if let Some(result) = self.received.remove(msg_id) {
+                return result;
+            }
+            if !self.pending.contains_key(msg_id) {
+                return Err(Error::UnknownMessageId(msg_id.to_string()));
+            }
+            match self.read(deadline) {
+                Ok(WsMessage::Text(text)) => self.dispatch(text.as_str()),
+                Ok(WsMessage::Binary(data)) => self.dispatch_binary(data),
+                Ok(_) => {}
+                Err(Error::Timeout) => {
+                    self.pending.remove(msg_id);
+                    return Err(Error::Timeout);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn read(&mut self, deadline: Option<Instant>) -> Result<WsMessage> {
+        let timeout = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(Error::Timeout);
+                }
+                Some(remaining)
+            }
+            None => None,
+        };
+        match self.socket.get_mut() {
+            MaybeTlsStream::Plain(stream) => stream.set_read_timeout(timeout)?,
+            // TLS streams only exist if another crate enables tungstenite's TLS features.
+            _ if timeout.is_some() => return Err(Error::UnsupportedTimeout),
+            _ => {}
+        }
+        // A read that times out leaves partially received frames buffered, so it can simply be retried.
+        Ok(self.socket.read()?)
+    }
+
+    fn dispatch(&mut self, text: &str) {
+        if let Some(resp) = self.awaiting_binary.take() {
+            self.complete(resp.message_id().to_string(), Err(Error::MissingBinary));
+        }
+        match Response::deserialize(text) {
+            Ok(resp) if self.pending.get(resp.message_id()).is_some_and(|pending| pending.expects_binary) => {
+                self.awaiting_binary = Some(resp);
+            }
+            Ok(resp) => self.complete(resp.message_id().to_string(), Ok((resp, None))),
+            Err(error) => {
+                if let Some(msg_id) = Response::peek_message_id(text) {
+                    self.complete(msg_id, Err(Error::Decode { error, text: text.to_string() }));
+                }
+            }
+        }
+    }
+
+    fn dispatch_binary(&mut self, data: Bytes) {
+        if let Some(resp) = self.awaiting_binary.take() {
+            self.complete(resp.message_id().to_string(), Ok((resp, Some(data))));
+        }
+    }
+
+    /// Keeps the result for `receive`, unless nobody is waiting for it anymore.
+    fn complete(&mut self, msg_id: String, result: Result<(Response, Option<Bytes>)>) {
+        let Some(pending) = self.pending.remove(&msg_id) else {
+            return;
+        };
+        if let Ok((response, _)) = &result {
+            let latency = pending.sent.elapsed();
+            for interceptor in self.interceptors.iter_mut().rev() {
+                interceptor.after_receive(response, latency);
+            }
+        }
+        self.received.insert(msg_id, result);
+    }
+
+    pub fn call(&mut self, msg: Message, data: Option<Bytes>) -> Result<Response> {
+        let msg_id = self.send(msg, data)?;
+        self.receive(&msg_id)
+    }
+
+    /// Sends with `send_expecting_binary` and waits for the response and its binary frame.
+    pub fn call_with_binary(&mut self, msg: Message, data: Option<Bytes>) -> Result<(Response, Option<Bytes>)> {
+        let msg_id = self.send_expecting_binary(msg, data)?;
+        self.receive_with_binary(&msg_id)
+    }
+
+    pub fn call_with_timeout(&mut self, msg: Message, data: Option<Bytes>, timeout: Duration) -> Result<Response> {
+        let msg_id = self.send(msg, data)?;
+        self.receive_with_timeout(&msg_id, timeout)
+    }
+
+    pub fn request<R: Request>(&mut self, request: R, data: Option<Bytes>) -> Result<R::Response> {
+        let msg: Message = request.into();
+        let kind = msg.kind();
+        let response = self.call(msg, data)?.into_result().map_err(|e| e.with_message_kind(kind))?;
+        R::Response::try_from(response).map_err(|response| Error::UnexpectedResponse(Box::new(response)))
+    }
+
+    /// Performs the close handshake. Responses that haven't been received are lost.
+    pub fn close(mut self) -> Result<()> {
+        self.socket.close(None)?;
+        let deadline = self.timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        loop {
+            match self.read(deadline) {
+                Ok(_) => {}
+                Err(Error::Closed) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
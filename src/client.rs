@@ -1,26 +1,256 @@
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
 use std::fmt::Display;
-use futures_util::{FutureExt, SinkExt};
-use futures_util::stream::StreamExt;
+use std::hash::{BuildHasher, Hasher};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use futures_util::{future, FutureExt, SinkExt};
+use futures_util::stream::{SplitSink, StreamExt};
 use futures_util::select;
 use resoxide_json::Json;
+use tokio::io::{AsyncRead, AsyncReadExt};
 use tokio_tungstenite::tungstenite::{
+    handshake::client::Request as WsRequest,
+    http::header::IntoHeaderName,
+    protocol::frame::{coding::{Data, OpCode}, Frame},
+    Message as WsMessage,
+};
+pub use tokio_tungstenite::tungstenite::{
+    Bytes,
     client::IntoClientRequest,
+    http::HeaderValue,
     protocol::WebSocketConfig,
-    Message as WsMessage,
 };
-use crate::messages::Message;
-use crate::responses::Response;
+use crate::audio::AudioError;
+use crate::data_model::{ComponentDefinition, Member, MemberError};
+use crate::mesh::{MeshData, MeshError};
+use crate::messages::{AddComponent, AddComponentData, AddSlot, AddSlotData, GetComponent, GetComponentDefinition, GetEnumDefinition, GetSlot, ImportAudioClipFile, ImportMeshFile, ImportTexture2DFile, ImportTexture2DRawData, ImportTexture2DRawDataHDR, Message, PayloadError, RemoveComponent, RemoveSlot, Request, RequestSessionData, UpdateComponent, UpdateComponentData, UpdateSlot, UpdateSlotData};
+use crate::responses::{AssetData, ComponentData, ComponentDefinitionData, EnumDefinitionData, Response, ResponseData, ServerError, SessionData, SlotData};
+pub use crate::messages::{MessageIdGenerator, SequentialIds};
+#[cfg(feature = "uuid")]
+pub use crate::messages::UuidIds;
 
 enum Handle {
     Sync(std::thread::JoinHandle<Result<()>>),
     Tokio(tokio::task::JoinHandle<Result<()>>,tokio::runtime::Handle),
 }
 
+type WebSocket = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// The interactive and bulk lanes, interactive commands are always taken first.
+type Commands = futures_util::stream::SelectWithStrategy<
+    tokio_stream::wrappers::ReceiverStream<Command>,
+    tokio_stream::wrappers::ReceiverStream<Command>,
+    fn(&mut ()) -> futures_util::stream::PollNext,
+    (),
+>;
+
+/// Owns the connection task. Dereferences to a `ClientHandle` that can be cloned and shared
+/// between tasks; the connection stays open until `close` is called or every handle is dropped.
 pub struct Client {
+    handle: ClientHandle,
+    task: Option<Handle>,
+    close_tx: Option<tokio::sync::oneshot::Sender<CloseRequest>>,
+}
+
+#[derive(Clone)]
+pub struct ClientHandle {
     tx: tokio::sync::mpsc::Sender<Command>,
-    handle: Option<Handle>,
-    close_tx: Option<tokio::sync::oneshot::Sender<()>>,
+    bulk_tx: tokio::sync::mpsc::Sender<Command>,
+    priority: Priority,
+    state: tokio::sync::watch::Receiver<ConnectionState>,
+    default_timeout: Option<Duration>,
+    last_error: Arc<std::sync::Mutex<Option<Arc<Error>>>>,
+    round_trip_time: Arc<std::sync::Mutex<Option<Duration>>>,
+    session: Arc<std::sync::Mutex<Option<Arc<SessionData>>>>,
+    /// Component definitions by component type, see `component_definition`.
+    definitions: Arc<std::sync::Mutex<HashMap<String, Arc<ComponentDefinition>>>>,
+    limiter_stats: Arc<std::sync::Mutex<LimiterStats>>,
+    diagnostics: tokio::sync::broadcast::Sender<Diagnostic>,
+    incoming: tokio::sync::broadcast::Sender<Incoming>,
+}
+
+/// Which queue a `ClientHandle` sends its calls through. Each lane keeps its order, and bulk calls
+/// are only taken while no interactive call is waiting.
+#[derive(Debug,Clone,Copy,Default,PartialEq,Eq)]
+pub enum Priority {
+    #[default]
+    Interactive,
+    Bulk,
+}
+
+/// A response received on the connection, see `ClientHandle::subscribe`.
+#[derive(Debug,Clone)]
+pub struct Incoming {
+    pub response: Arc<Response>,
+    pub data: Option<Bytes>,
+    /// Whether the response answered a call, which received it as well.
+    pub matched: bool,
+}
+
+/// Problems on the connection that could not be reported to a specific call.
+#[derive(Debug,Clone)]
+pub enum Diagnostic {
+    /// A frame that failed to decode and whose `sourceMessageId` could not be recovered.
+    UndecodableFrame { error: Arc<resoxide_json::Error>, text: String },
+    /// A binary frame that didn't follow a response expecting one.
+    UnexpectedBinary(Bytes),
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Disconnected,
+}
+
+/// What happens to requests that are still waiting for a response when the connection is lost.
+#[derive(Debug,Clone,Copy,Default,PartialEq,Eq)]
+pub enum PendingPolicy {
+    /// Every pending request fails with `Error::Reconnected`.
+    #[default]
+    Fail,
+    /// Idempotent requests (see `Message::is_idempotent`) are sent again once reconnected, the rest fail.
+    ResendIdempotent,
+}
+
+#[derive(Debug,Clone)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    /// `None` retries forever.
+    pub max_attempts: Option<u32>,
+    /// Fraction (0 to 1) of each delay that is randomly taken off.
+    pub jitter: f64,
+    pub pending: PendingPolicy,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            max_attempts: None,
+            jitter: 0.2,
+            pending: PendingPolicy::default(),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// A multiplier below 1 or NaN keeps the delay constant, and the jitter is kept between 0 and 1
+    /// with NaN meaning none.
+    fn clamped(self) -> Self {
+        Self {
+            multiplier: if self.multiplier >= 1.0 { self.multiplier } else { 1.0 },
+            jitter: if self.jitter.is_nan() { 0.0 } else { self.jitter.clamp(0.0, 1.0) },
+            ..self
+        }
+    }
+
+    fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self.initial_delay.as_secs_f64() * self.multiplier.powi(attempt.try_into().unwrap_or(i32::MAX));
+        // NaN if a zero initial delay is multiplied by an infinite backoff.
+        let delay = if backoff.is_nan() { 0.0 } else { backoff.min(self.max_delay.as_secs_f64()) };
+        // Every `RandomState` is seeded differently, which is random enough to spread out reconnects
+        // without depending on an RNG.
+        let random = std::collections::hash_map::RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        // Fails if `max_delay` is too large for an f64 round trip, e.g. `Duration::MAX`.
+        Duration::try_from_secs_f64(delay * (1.0 - self.jitter * random)).unwrap_or(self.max_delay)
+    }
+}
+
+/// Pings the server regularly so that a connection that stopped responding is noticed.
+#[derive(Debug,Clone)]
+pub struct KeepalivePolicy {
+    /// Time between the pong and the next ping.
+    pub interval: Duration,
+    /// How long to wait for a pong before the connection fails with `Error::KeepaliveTimeout`.
+    pub timeout: Duration,
+}
+
+impl Default for KeepalivePolicy {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(15),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl KeepalivePolicy {
+    /// Longest interval and timeout that are enforced, longer ones are lowered to it.
+    pub const MAX_DURATION: Duration = Duration::from_secs(365 * 86400);
+
+    fn clamped(self) -> Self {
+        Self {
+            interval: self.interval.min(Self::MAX_DURATION),
+            timeout: self.timeout.min(Self::MAX_DURATION),
+        }
+    }
+}
+
+/// Limits on outgoing traffic. While a limit is reached the connection task stops taking commands,
+/// so calls wait for room in the queue instead of piling up.
+#[derive(Debug,Clone,Default)]
+pub struct RateLimit {
+    /// Allows bursts of up to one second's worth of messages.
+    pub messages_per_second: Option<f64>,
+    /// Requests sent and still waiting for their response.
+    pub max_in_flight: Option<usize>,
+    /// Binary payload bytes. A payload larger than a second's worth is sent whole, and the
+    /// following messages wait until it is paid off.
+    pub bytes_per_second: Option<f64>,
+}
+
+impl RateLimit {
+    /// Lowest rate that is enforced, smaller ones are raised to it.
+    pub const MIN_RATE: f64 = 0.001;
+
+    /// Infinite or NaN rates mean no limit, and at least one request may be in flight.
+    fn clamped(self) -> Self {
+        let rate = |rate: Option<f64>| rate.filter(|rate| rate.is_finite()).map(|rate| rate.max(Self::MIN_RATE));
+        Self {
+            messages_per_second: rate(self.messages_per_second),
+            max_in_flight: self.max_in_flight.map(|max| max.max(1)),
+            bytes_per_second: rate(self.bytes_per_second),
+        }
+    }
+}
+
+/// How long sent requests waited between the call and being taken by the connection task.
+#[derive(Debug,Clone,Copy,Default)]
+pub struct LimiterStats {
+    pub requests: u64,
+    pub total_wait: Duration,
+    pub max_wait: Duration,
+}
+
+impl LimiterStats {
+    pub fn average_wait(&self) -> Duration {
+        if self.requests == 0 {
+            return Duration::ZERO;
+        }
+        self.total_wait.div_f64(self.requests as f64)
+    }
+}
+
+/// Hooks that run on the connection task for every call, in the order they were added to the
+/// `ClientBuilder`. `after_receive` runs in reverse order. Both should return quickly.
+pub trait Interceptor: Send {
+    /// Called once the message has its id, right before it is sent. An error fails the call instead.
+    fn before_send(&mut self, msg: &mut Message, data: Option<&Payload>) -> Result<()> {
+        let _ = (msg, data);
+        Ok(())
+    }
+
+    /// Called with the response to a call, `latency` is the time since the message was sent.
+    fn after_receive(&mut self, response: &Response, latency: Duration) {
+        let _ = (response, latency);
+    }
 }
 
 #[derive(Debug)]
@@ -28,9 +258,34 @@ pub enum Error {
     Io(std::io::Error),
     WebSocket(tokio_tungstenite::tungstenite::error::Error),
     Closed,
+    Reconnected,
+    Timeout,
+    /// The server didn't answer a keepalive ping in time.
+    KeepaliveTimeout,
+    /// The connection was terminated by this error. Shared by every call that was affected by it.
+    Connection(Arc<Error>),
     Unknown,
     Json(resoxide_json::Error),
-    BinaryMismatch,
+    /// Another request with the same message id is still waiting for its response.
+    DuplicateMessageId(String),
+    /// The response to this call could not be decoded.
+    Decode { error: resoxide_json::Error, text: String },
+    /// The binary payload doesn't fit the message.
+    InvalidPayload(PayloadError),
+    /// The response should have been followed by a binary frame, but another frame came first.
+    MissingBinary,
+    /// Resonite reported that the request failed.
+    Server(ServerError),
+    /// The members don't match the component's definition.
+    InvalidMembers(MemberError),
+    /// The mesh data can't be encoded.
+    InvalidMesh(MeshError),
+    /// The audio samples can't be encoded.
+    InvalidAudio(AudioError),
+    /// An interceptor refused to send the message.
+    Rejected(String),
+    /// The response wasn't of the type the request is answered with.
+    UnexpectedResponse(Box<Response>),
 }
 
 impl Display for Error {
@@ -65,6 +320,36 @@ impl From<tokio_tungstenite::tungstenite::error::Error> for Error {
     }
 }
 
+impl From<ServerError> for Error {
+    fn from(e: ServerError) -> Self {
+        Self::Server(e)
+    }
+}
+
+impl From<PayloadError> for Error {
+    fn from(e: PayloadError) -> Self {
+        Self::InvalidPayload(e)
+    }
+}
+
+impl From<MemberError> for Error {
+    fn from(e: MemberError) -> Self {
+        Self::InvalidMembers(e)
+    }
+}
+
+impl From<MeshError> for Error {
+    fn from(e: MeshError) -> Self {
+        Self::InvalidMesh(e)
+    }
+}
+
+impl From<AudioError> for Error {
+    fn from(e: AudioError) -> Self {
+        Self::InvalidAudio(e)
+    }
+}
+
 impl From<resoxide_json::Error> for Error {
     fn from(e: resoxide_json::Error) -> Self {
         Self::Json(e)
@@ -73,208 +358,1909 @@ impl From<resoxide_json::Error> for Error {
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
-impl Client {
-    fn client_task(request: tokio_tungstenite::tungstenite::handshake::client::Request, close_rx: tokio::sync::oneshot::Receiver<()>, rx: tokio::sync::mpsc::Receiver<Command>, resp_tx: tokio::sync::oneshot::Sender<()>) -> impl Future<Output = Result<()>> {
-        async move {
-            let conf = WebSocketConfig::default();
-            let (websocket, _) = tokio_tungstenite::connect_async_with_config(request, Some(conf), true).await?;
-            let (mut sink, stream) = websocket.split();
-            let mut stream = stream.fuse();
-            let mut rx = tokio_stream::wrappers::ReceiverStream::new(rx).fuse();
-            let mut closer = close_rx.fuse();
-            let mut counter = 0usize;
-            let mut responders: HashMap<String, tokio::sync::oneshot::Sender<Response>> = HashMap::new();
-            let _ = resp_tx.send(());
-            loop {
-                select! {
-                        msg = stream.next() => {
-                            match msg {
-                                None => return Err(Error::Closed),
-                                Some(Err(e)) => return Err(Error::WebSocket(e)),
-                                Some(Ok(WsMessage::Binary(_))) => {}
-                                Some(Ok(WsMessage::Text(text))) => {
-                                    let resp = Response::deserialize(text.as_str())?;
-                                    if let Some(responder) = responders.remove(resp.message_id()) {
-                                        let _ = responder.send(resp);
-                                    }
-                                },
-                                Some(Ok(WsMessage::Close(_))) => return Ok(()),
-                                _ => (),
-                            }
-                        },
-                        cmd = rx.next() => {
-                            match cmd {
-                                None => return Err(Error::Closed),
-                                Some(Command { msg, resp, data }) => {
-                                    counter += 1;
-                                    let msg_id = format!("Msg{}", counter);
-                                    let msg = msg.with_message_id(msg_id.clone()).to_token()?.serialize()?;
-                                    let ws_msg = WsMessage::text(msg);
-                                    responders.insert(msg_id, resp);
-                                    sink.send(ws_msg).await?;
-                                    if let Some(binary) = data {
-                                        sink.send(WsMessage::binary(binary)).await?;
-                                    }
-                                    sink.flush().await?;
-                                }
-                            }
-                        },
-                        closed = &mut closer => {
-                            let mut websocket = sink.reunite(stream.into_inner()).unwrap();
-                            websocket.close(None).await?;
-                            return Ok(closed?);
-                        }
-                    }
-            }
+/// Binary data sent after a message, see `Message::has_binary`.
+pub enum Payload {
+    Bytes(Bytes),
+    /// Read and sent in chunks while the message is sent. The reader must provide exactly `len` bytes,
+    /// otherwise the connection is dropped since the partially sent frame can't be taken back.
+    Reader { reader: Pin<Box<dyn AsyncRead + Send>>, len: usize },
+}
+
+impl Payload {
+    const CHUNK_SIZE: usize = 64 * 1024;
+
+    pub fn from_reader<R: AsyncRead + Send + 'static>(reader: R, len: usize) -> Self {
+        Self::Reader { reader: Box::pin(reader), len }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Payload::Bytes(bytes) => bytes.len(),
+            Payload::Reader { len, .. } => *len,
         }
     }
 
-    fn connect_impl(request: tokio_tungstenite::tungstenite::handshake::client::Request, close_rx: tokio::sync::oneshot::Receiver<()>, rx: tokio::sync::mpsc::Receiver<Command>) -> Result<(std::thread::JoinHandle<Result<()>>,tokio::sync::oneshot::Receiver<()>)> {
-        let (resp_tx, resp_rx) = tokio::sync::oneshot::channel();
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
-        let handle = std::thread::spawn(move || {
-            let runtime = tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()?;
-            runtime.block_on(Self::client_task(request, close_rx, rx, resp_tx))
-        });
-        Ok((handle, resp_rx))
+    /// Checks that the payload is present and sized as `msg` requires.
+    fn validate(msg: &Message, payload: Option<&Payload>) -> Result<()> {
+        Ok(msg.check_payload_len(payload.map(Payload::len))?)
     }
+}
 
-    fn connect_async(request: tokio_tungstenite::tungstenite::handshake::client::Request, close_rx: tokio::sync::oneshot::Receiver<()>, rx: tokio::sync::mpsc::Receiver<Command>) -> Result<(tokio::task::JoinHandle<Result<()>>,tokio::sync::oneshot::Receiver<()>)> {
-        let (resp_tx, resp_rx) = tokio::sync::oneshot::channel();
+impl std::fmt::Debug for Payload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Payload::Bytes(bytes) => f.debug_tuple("Bytes").field(&bytes.len()).finish(),
+            Payload::Reader { len, .. } => f.debug_struct("Reader").field("len", len).finish_non_exhaustive(),
+        }
+    }
+}
+
+impl From<Bytes> for Payload {
+    fn from(bytes: Bytes) -> Self {
+        Self::Bytes(bytes)
+    }
+}
 
-        let handle = tokio::spawn(Self::client_task(request, close_rx, rx, resp_tx));
+impl From<Vec<u8>> for Payload {
+    fn from(data: Vec<u8>) -> Self {
+        Self::Bytes(data.into())
+    }
+}
 
-        Ok((handle, resp_rx))
+impl From<Arc<[u8]>> for Payload {
+    fn from(data: Arc<[u8]>) -> Self {
+        Self::Bytes(Bytes::from_owner(data))
     }
+}
 
-    pub async fn connect_port(port: u16) -> Result<Client> {
-        let request = format!("ws://localhost:{}", port).into_client_request()?;
-        let (tx, rx) = tokio::sync::mpsc::channel::<Command>(8);
+impl From<&'static [u8]> for Payload {
+    fn from(data: &'static [u8]) -> Self {
+        Self::Bytes(Bytes::from_static(data))
+    }
+}
+
+/// Where the connection task runs.
+#[derive(Debug,Clone,Default)]
+pub enum Executor {
+    /// `connect` spawns onto the current runtime if it is called from a multi-thread runtime and uses
+    /// `Thread` otherwise, since a current-thread runtime only runs the task while it is being blocked on.
+    /// `blocking_connect` always uses `Thread`.
+    #[default]
+    Auto,
+    /// A dedicated OS thread running its own current-thread runtime.
+    Thread,
+    /// Spawned onto the given runtime, which has to keep running for as long as the client is used.
+    Runtime(tokio::runtime::Handle),
+}
+
+pub struct ClientBuilder {
+    request: WsRequest,
+    config: WebSocketConfig,
+    channel_capacity: usize,
+    reconnect: Option<ReconnectPolicy>,
+    keepalive: Option<KeepalivePolicy>,
+    timeout: Option<Duration>,
+    ids: Box<dyn MessageIdGenerator>,
+    executor: Executor,
+    copy_matched: bool,
+    interceptors: Vec<Box<dyn Interceptor>>,
+    rate_limit: Option<RateLimit>,
+    session_timeout: Option<Duration>,
+}
+
+struct TaskOptions {
+    request: WsRequest,
+    config: WebSocketConfig,
+    reconnect: Option<ReconnectPolicy>,
+    keepalive: Option<KeepalivePolicy>,
+    copy_matched: bool,
+    session_timeout: Option<Duration>,
+}
+
+impl ClientBuilder {
+    pub const DEFAULT_CHANNEL_CAPACITY: usize = 8;
+    /// Short, since it delays every connection to a server that doesn't answer the request.
+    pub const DEFAULT_SESSION_TIMEOUT: Duration = Duration::from_secs(1);
+    const DIAGNOSTICS_CAPACITY: usize = 64;
+    const INCOMING_CAPACITY: usize = 64;
+
+    pub fn new<R: IntoClientRequest>(request: R) -> Result<ClientBuilder> {
+        Ok(ClientBuilder {
+            request: request.into_client_request()?,
+            config: WebSocketConfig::default(),
+            channel_capacity: Self::DEFAULT_CHANNEL_CAPACITY,
+            reconnect: None,
+            keepalive: None,
+            timeout: None,
+            ids: Box::new(SequentialIds::default()),
+            executor: Executor::default(),
+            copy_matched: false,
+            interceptors: vec![],
+            rate_limit: None,
+            session_timeout: Some(Self::DEFAULT_SESSION_TIMEOUT),
+        })
+    }
+
+    pub fn port(port: u16) -> Result<ClientBuilder> {
+        Self::new(format!("ws://localhost:{}", port))
+    }
+
+    pub fn header<K: IntoHeaderName>(mut self, name: K, value: HeaderValue) -> Self {
+        self.request.headers_mut().insert(name, value);
+        self
+    }
+
+    pub fn websocket_config(mut self, config: WebSocketConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn max_message_size(mut self, max_message_size: Option<usize>) -> Self {
+        self.config = self.config.max_message_size(max_message_size);
+        self
+    }
+
+    pub fn max_frame_size(mut self, max_frame_size: Option<usize>) -> Self {
+        self.config = self.config.max_frame_size(max_frame_size);
+        self
+    }
+
+    /// Number of commands that can be queued in each `Priority` lane before `call` waits.
+    pub fn channel_capacity(mut self, channel_capacity: usize) -> Self {
+        self.channel_capacity = channel_capacity.max(1);
+        self
+    }
+
+    /// Re-establish the connection when it is lost. The initial connection is never retried.
+    /// A `multiplier` below 1 keeps the delay constant, and `jitter` is clamped to 0 to 1.
+    pub fn reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = Some(policy.clamped());
+        self
+    }
+
+    /// Send pings while connected and treat a missing pong as a lost connection. Durations above
+    /// `KeepalivePolicy::MAX_DURATION` are lowered to it.
+    pub fn keepalive(mut self, policy: KeepalivePolicy) -> Self {
+        self.keepalive = Some(policy.clamped());
+        self
+    }
+
+    /// Default timeout for `call` and `blocking_call`, measured from the moment the call is made.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// How ids are assigned to messages that are sent without one. Ids set by the caller are kept.
+    pub fn message_ids<G: MessageIdGenerator + 'static>(mut self, ids: G) -> Self {
+        self.ids = Box::new(ids);
+        self
+    }
+
+    pub fn executor(mut self, executor: Executor) -> Self {
+        self.executor = executor;
+        self
+    }
+
+    /// Runs the connection task on `runtime` instead of a dedicated thread, see `Executor::Runtime`.
+    pub fn runtime(self, runtime: tokio::runtime::Handle) -> Self {
+        self.executor(Executor::Runtime(runtime))
+    }
+
+    /// Also send responses that answered a call to `ClientHandle::subscribe`. Each copy is decoded
+    /// separately, and only while there are subscribers.
+    pub fn copy_matched_responses(mut self, copy_matched: bool) -> Self {
+        self.copy_matched = copy_matched;
+        self
+    }
+
+    /// Adds an interceptor to the chain, see `Interceptor`.
+    pub fn interceptor<I: Interceptor + 'static>(mut self, interceptor: I) -> Self {
+        self.interceptors.push(Box::new(interceptor));
+        self
+    }
+
+    /// Limits outgoing traffic. Rates below `RateLimit::MIN_RATE` are raised to it, and a
+    /// `max_in_flight` of zero allows one request.
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = Some(rate_limit.clamped());
+        self
+    }
+
+    /// How long a new connection waits for the session data before other requests are sent on it,
+    /// `DEFAULT_SESSION_TIMEOUT` unless set. `None` doesn't ask for it, which avoids the wait on
+    /// servers that never answer.
+    pub fn session_info(mut self, timeout: Option<Duration>) -> Self {
+        self.session_timeout = timeout;
+        self
+    }
+
+    fn build(self) -> (Client, ClientTask) {
+        let (tx, rx) = tokio::sync::mpsc::channel::<Command>(self.channel_capacity);
+        let (bulk_tx, bulk_rx) = tokio::sync::mpsc::channel::<Command>(self.channel_capacity);
         let (close_tx, close_rx) = tokio::sync::oneshot::channel();
-        if tokio::task::try_id().is_none() {
-            let (handle, resp_rx) = Self::connect_impl(request, close_rx, rx)?;
-            match resp_rx.await {
-                Ok(_) => Ok(Client { tx, handle: Some(Handle::Sync(handle)), close_tx: Some(close_tx) }),
-                Err(_) => {
-                    handle.join().unwrap()?;
-                    Err(Error::Unknown)
-                }
+        let (state_tx, state) = tokio::sync::watch::channel(ConnectionState::Connecting);
+        let last_error = Arc::new(std::sync::Mutex::new(None));
+        let round_trip_time = Arc::new(std::sync::Mutex::new(None));
+        let session = Arc::new(std::sync::Mutex::new(None));
+        let limiter_stats = Arc::new(std::sync::Mutex::new(LimiterStats::default()));
+        let (diagnostics, _) = tokio::sync::broadcast::channel(ClientBuilder::DIAGNOSTICS_CAPACITY);
+        let (incoming, _) = tokio::sync::broadcast::channel(ClientBuilder::INCOMING_CAPACITY);
+        let client = Client {
+            handle: ClientHandle {
+                tx,
+                bulk_tx,
+                priority: Priority::default(),
+                state,
+                default_timeout: self.timeout,
+                last_error: last_error.clone(),
+                round_trip_time: round_trip_time.clone(),
+                session: session.clone(),
+                definitions: Arc::new(std::sync::Mutex::new(HashMap::new())),
+                limiter_stats: limiter_stats.clone(),
+                diagnostics: diagnostics.clone(),
+                incoming: incoming.clone(),
+            },
+            task: None,
+            close_tx: Some(close_tx),
+        };
+        let task = ClientTask {
+            options: TaskOptions {
+                request: self.request,
+                config: self.config,
+                reconnect: self.reconnect,
+                keepalive: self.keepalive,
+                copy_matched: self.copy_matched,
+                session_timeout: self.session_timeout,
+            },
+            state_tx,
+            rx: futures_util::stream::select_with_strategy(
+                tokio_stream::wrappers::ReceiverStream::new(rx),
+                tokio_stream::wrappers::ReceiverStream::new(bulk_rx),
+                (|_| futures_util::stream::PollNext::Left) as fn(&mut ()) -> futures_util::stream::PollNext,
+            ).fuse(),
+            held: None,
+            closer: close_rx.fuse(),
+            ids: self.ids,
+            interceptors: self.interceptors,
+            limiter: self.rate_limit.map(|limit| Limiter::new(limit, limiter_stats, tokio::time::Instant::now())),
+            responders: Responders::new(),
+            awaiting_binary: None,
+            last_error,
+            round_trip_time,
+            session,
+            diagnostics,
+            incoming,
+        };
+        (client, task)
+    }
+
+    fn spawn(executor: Executor, task: ClientTask, blocking: bool) -> Result<(Handle, tokio::sync::oneshot::Receiver<()>)> {
+        let runtime = match executor {
+            Executor::Auto if !blocking => tokio::runtime::Handle::try_current().ok()
+                .filter(|runtime| runtime.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread),
+            Executor::Auto | Executor::Thread => None,
+            Executor::Runtime(runtime) => Some(runtime),
+        };
+        match runtime {
+            Some(runtime) => {
+                let (handle, resp_rx) = Client::connect_async(task, &runtime)?;
+                Ok((Handle::Tokio(handle, runtime), resp_rx))
             }
-        } else {
-            let (handle, resp_rx) = Self::connect_async(request, close_rx, rx)?;
-            match resp_rx.await {
-                Ok(_) => Ok(Client { tx, handle: Some(Handle::Tokio(handle, tokio::runtime::Handle::current())), close_tx: Some(close_tx) }),
-                Err(_) => {
-                    handle.await.unwrap()?;
-                    Err(Error::Unknown)
-                }
+            None => {
+                let (handle, resp_rx) = Client::connect_impl(task)?;
+                Ok((Handle::Sync(handle), resp_rx))
             }
         }
     }
 
-    pub fn blocking_connect_port(port: u16) -> Result<Client> {
-        let request = format!("ws://localhost:{}", port).into_client_request()?;
-        let (tx, rx) = tokio::sync::mpsc::channel::<Command>(8);
-        let (close_tx, close_rx) = tokio::sync::oneshot::channel();
-        let (handle, resp_rx) = Self::connect_impl(request, close_rx, rx)?;
-        match resp_rx.blocking_recv() {
-            Ok(_) => Ok(Client { tx, handle: Some(Handle::Sync(handle)), close_tx: Some(close_tx) }),
-            Err(_) => {
-                handle.join().unwrap()?;
-                Err(Error::Unknown)
-            }
+    /// Connects and starts the connection task on the executor chosen with `executor`.
+    pub async fn connect(mut self) -> Result<Client> {
+        let executor = std::mem::take(&mut self.executor);
+        let (mut client, task) = self.build();
+        let (handle, resp_rx) = Self::spawn(executor, task, false)?;
+        client.task = Some(handle);
+        if resp_rx.await.is_err() {
+            client.join().await?;
+            return Err(Error::Unknown);
         }
+        Ok(client)
     }
 
-    pub async fn close(mut self) -> Result<()> {
-        if let Some(close_tx) = self.close_tx.take() {
-            let _ = close_tx.send(());
+    /// Connects and starts the connection task on the executor chosen with `executor`.
+    /// Must not be called from within an async context.
+    pub fn blocking_connect(mut self) -> Result<Client> {
+        let executor = std::mem::take(&mut self.executor);
+        let (mut client, task) = self.build();
+        let (handle, resp_rx) = Self::spawn(executor, task, true)?;
+        client.task = Some(handle);
+        if resp_rx.blocking_recv().is_err() {
+            client.blocking_join()?;
+            return Err(Error::Unknown);
         }
-        self.tx.closed().await;
-        match self.handle.take() {
-            Some(Handle::Sync(handle)) => {
-                handle.join().unwrap()?;
+        Ok(client)
+    }
+}
+
+struct ClientTask {
+    options: TaskOptions,
+    state_tx: tokio::sync::watch::Sender<ConnectionState>,
+    rx: futures_util::stream::Fuse<Commands>,
+    /// A command taken from `rx` while reconnecting, sent before any other once connected.
+    held: Option<Command>,
+    closer: futures_util::future::Fuse<tokio::sync::oneshot::Receiver<CloseRequest>>,
+    ids: Box<dyn MessageIdGenerator>,
+    interceptors: Vec<Box<dyn Interceptor>>,
+    limiter: Option<Limiter>,
+    responders: Responders,
+    /// A response that is waiting for its binary frame.
+    awaiting_binary: Option<AwaitingBinary>,
+    last_error: Arc<std::sync::Mutex<Option<Arc<Error>>>>,
+    round_trip_time: Arc<std::sync::Mutex<Option<Duration>>>,
+    session: Arc<std::sync::Mutex<Option<Arc<SessionData>>>>,
+    diagnostics: tokio::sync::broadcast::Sender<Diagnostic>,
+    incoming: tokio::sync::broadcast::Sender<Incoming>,
+}
+
+struct AwaitingBinary {
+    response: Response,
+    /// Copy for subscribers, see `ClientBuilder::copy_matched_responses`.
+    copy: Option<Arc<Response>>,
+}
+
+impl ClientTask {
+    async fn run(mut self, resp_tx: tokio::sync::oneshot::Sender<()>) -> Result<()> {
+        let mut websocket = match tokio_tungstenite::connect_async_with_config(self.options.request.clone(), Some(self.options.config), true).await {
+            Ok((websocket, _)) => websocket,
+            Err(e) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(uri = %self.options.request.uri(), error = %e, "connection failed");
+                self.state_tx.send_replace(ConnectionState::Disconnected);
+                return Err(e.into());
             }
-            Some(Handle::Tokio(handle, _)) => {
-                handle.await.unwrap()?;
+        };
+        self.fetch_session(&mut websocket).await;
+        #[cfg(feature = "tracing")]
+        tracing::info!(uri = %self.options.request.uri(), "connected");
+        self.state_tx.send_replace(ConnectionState::Connected);
+        let _ = resp_tx.send(());
+        let result = self.drive(websocket).await;
+        self.state_tx.send_replace(ConnectionState::Disconnected);
+        match result {
+            Ok(()) => {
+                // An error that only interrupted the connection doesn't explain why it was closed.
+                *self.last_error.lock().unwrap() = None;
+                Ok(())
+            }
+            Err(e) => {
+                #[cfg(feature = "tracing")]
+                tracing::error!(error = %e, "connection terminated");
+                let e = Arc::new(e);
+                *self.last_error.lock().unwrap() = Some(e.clone());
+                self.responders.fail_all(|| Error::Connection(e.clone()));
+                Err(Error::Connection(e))
             }
-            None => {}
         }
-        Ok(())
     }
 
-    pub fn blocking_close(mut self) -> Result<()> {
-        if let Some(close_tx) = self.close_tx.take() {
-            let _ = close_tx.send(());
+    async fn drive(&mut self, mut websocket: WebSocket) -> Result<()> {
+        let mut replay = vec![];
+        loop {
+            let result = match self.run_connection(websocket, replay).await? {
+                Disconnect::Local => return Ok(()),
+                Disconnect::Remote(result) => result,
+            };
+            #[cfg(feature = "tracing")]
+            match &result {
+                Ok(()) => tracing::info!("disconnected by the server"),
+                Err(e) => tracing::warn!(error = %e, "disconnected"),
+            }
+            let Some(policy) = self.options.reconnect.clone() else {
+                return result;
+            };
+            if let Err(e) = result {
+                *self.last_error.lock().unwrap() = Some(Arc::new(e));
+            }
+            self.state_tx.send_replace(ConnectionState::Connecting);
+            replay = self.responders.take_replay(policy.pending);
+            websocket = match self.reconnect(&policy).await? {
+                Some(websocket) => websocket,
+                None => return Ok(()),
+            };
+            *self.last_error.lock().unwrap() = None;
+            self.fetch_session(&mut websocket).await;
+            #[cfg(feature = "tracing")]
+            tracing::info!(uri = %self.options.request.uri(), replayed = replay.len(), "reconnected");
+            self.state_tx.send_replace(ConnectionState::Connected);
         }
-        match self.handle.take() {
-            Some(Handle::Sync(handle)) => {
-                handle.join().unwrap()?;
+    }
+
+    /// Asks for the session data on a new connection, before any other request is sent on it.
+    /// Failures leave the session data empty, since older servers might not answer at all.
+    async fn fetch_session(&mut self, websocket: &mut WebSocket) {
+        *self.session.lock().unwrap() = None;
+        let Some(timeout) = self.options.session_timeout else {
+            return;
+        };
+        let msg_id = self.ids.next_id();
+        let fetch = async {
+            let text = Message::from(RequestSessionData { message_id: msg_id.clone() }).serialize()?;
+            websocket.send(WsMessage::text(text)).await?;
+            loop {
+                let text = match websocket.next().await {
+                    Some(Ok(WsMessage::Text(text))) => text,
+                    Some(Ok(WsMessage::Close(_))) | None => return Err(Error::Closed),
+                    Some(Err(e)) => return Err(e.into()),
+                    Some(Ok(_)) => continue,
+                };
+                if Response::peek_message_id(text.as_str()).as_deref() != Some(msg_id.as_str()) {
+                    // Nothing else has been sent yet, so this was initiated by the server.
+                    self.dispatch(text.as_str());
+                    continue;
+                }
+                let response = Response::deserialize(text.as_str())?.into_result()?;
+                return SessionData::try_from(response).map_err(|response| Error::UnexpectedResponse(Box::new(response)));
             }
-            Some(Handle::Tokio(handle, rt)) => {
-                rt.block_on(async move {
-                    handle.await.unwrap()
-                })?;
+        };
+        match tokio::time::timeout(timeout, fetch).await {
+            Ok(Ok(session)) => {
+                *self.session.lock().unwrap() = Some(Arc::new(session));
             }
-            None => {}
+            #[cfg(feature = "tracing")]
+            Ok(Err(e)) => tracing::warn!(error = %e, "session data not available"),
+            #[cfg(feature = "tracing")]
+            Err(_) => tracing::warn!("session data not available in time"),
+            #[cfg(not(feature = "tracing"))]
+            _ => {}
         }
-        Ok(())
     }
 
-    pub fn is_active(&self) -> bool {
-        match &self.handle {
-            Some(Handle::Sync(handle)) => !handle.is_finished(),
-            Some(Handle::Tokio(handle, _)) => !handle.is_finished(),
-            None => false,
+    async fn reconnect(&mut self, policy: &ReconnectPolicy) -> Result<Option<WebSocket>> {
+        let mut attempt = 0u32;
+        loop {
+            let delay = policy.delay(attempt);
+            attempt += 1;
+            #[cfg(feature = "tracing")]
+            tracing::debug!(attempt, delay_ms = delay.as_secs_f64() * 1000.0, "reconnecting");
+            let request = self.options.request.clone();
+            let config = self.options.config;
+            let connect = async move {
+                tokio::time::sleep(delay).await;
+                tokio_tungstenite::connect_async_with_config(request, Some(config), true).await
+            };
+            let mut connect = Box::pin(connect).fuse();
+            loop {
+                let wakeup = self.responders.wakeup();
+                select! {
+                    result = connect => {
+                        match result {
+                            Ok((websocket, _)) => return Ok(Some(websocket)),
+                            Err(e) if policy.max_attempts.is_some_and(|max| attempt >= max) => return Err(e.into()),
+                            Err(_) => break,
+                        }
+                    },
+                    _ = wakeup.fuse() => {
+                        self.responders.expire(tokio::time::Instant::now());
+                    },
+                    // Taking a command is the only way to notice that every handle is gone, so one
+                    // is held back for the new connection while the rest stay in the queue.
+                    cmd = if self.held.is_none() { self.rx.next().left_future() } else { future::pending().right_future() } => {
+                        match cmd {
+                            Some(cmd) => self.held = Some(cmd),
+                            None => {
+                                self.responders.fail_all(|| Error::Closed);
+                                #[cfg(feature = "tracing")]
+                                tracing::info!("every handle dropped while reconnecting");
+                                return Ok(None);
+                            }
+                        }
+                    },
+                    _ = match &mut self.held {
+                        Some(cmd) => cmd.resp.closed().left_future(),
+                        None => future::pending().right_future(),
+                    }.fuse() => {
+                        // The caller stopped waiting, so the queue can be watched again.
+                        self.held = None;
+                    },
+                    closed = &mut self.closer => {
+                        if let Ok(close) = closed {
+                            let abandoned = self.stop_accepting() + self.responders.len();
+                            self.responders.fail_all(|| Error::Closed);
+                            #[cfg(feature = "tracing")]
+                            tracing::info!(abandoned, "closed while reconnecting");
+                            let _ = close.abandoned.send(abandoned);
+                            return Ok(None);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    async fn run_connection(&mut self, websocket: WebSocket, replay: Vec<String>) -> Result<Disconnect> {
+        let (mut sink, stream) = websocket.split();
+        let mut stream = stream.fuse();
+        let mut keepalive = Keepalive::new(self.options.keepalive.clone(), tokio::time::Instant::now());
+        self.awaiting_binary = None;
+        for text in replay {
+            if let Err(e) = Self::send_message(&mut sink, text, None).await {
+                return Ok(Disconnect::Remote(Err(e)));
+            }
+        }
+        loop {
+            let wakeup = self.responders.wakeup();
+            let ping = keepalive.wakeup();
+            let throttle = match &mut self.limiter {
+                Some(limiter) => limiter.throttle(tokio::time::Instant::now(), self.responders.len()),
+                None => Throttle::Open,
+            };
+            let throttle_wakeup = async move {
+                match throttle {
+                    Throttle::Until(until) => tokio::time::sleep_until(until).await,
+                    // Waiting for a response, which is handled by another branch.
+                    Throttle::Open | Throttle::InFlight => std::future::pending().await,
+                }
+            };
+            select! {
+                    msg = stream.next() => {
+                        match msg {
+                            None => return Ok(Disconnect::Remote(Err(Error::Closed))),
+                            Some(Err(e)) => return Ok(Disconnect::Remote(Err(Error::WebSocket(e)))),
+                            Some(Ok(WsMessage::Binary(data))) => self.dispatch_binary(data),
+                            Some(Ok(WsMessage::Text(text))) => self.dispatch(text.as_str()),
+                            Some(Ok(WsMessage::Pong(payload))) => {
+                                if let Some(rtt) = keepalive.pong(&payload, tokio::time::Instant::now()) {
+                                    *self.round_trip_time.lock().unwrap() = Some(rtt);
+                                }
+                            }
+                            Some(Ok(WsMessage::Close(_))) => return Ok(Disconnect::Remote(Ok(()))),
+                            _ => (),
+                        }
+                    },
+                    cmd = if matches!(throttle, Throttle::Open) {
+                        let (held, rx) = (&mut self.held, &mut self.rx);
+                        future::poll_fn(move |cx| match held.take() {
+                            Some(cmd) => std::task::Poll::Ready(Some(cmd)),
+                            None => rx.poll_next_unpin(cx),
+                        }).fuse().left_future()
+                    } else {
+                        future::pending().right_future()
+                    } => {
+                        match cmd {
+                            None => {
+                                // Every handle is gone, so nothing can use the connection anymore.
+                                self.shutdown(sink, stream, None).await?;
+                                return Ok(Disconnect::Local);
+                            }
+                            Some(Command { resp, deadline: Some(deadline), .. }) if deadline <= tokio::time::Instant::now() => {
+                                let _ = resp.send(Err(Error::Timeout));
+                            }
+                            Some(Command { msg, resp, data, deadline, queued, expects_binary, #[cfg(feature = "tracing")] span }) => {
+                                let mut msg = if msg.message_id().is_empty() {
+                                    msg.with_message_id(self.ids.next_id())
+                                } else {
+                                    msg
+                                };
+                                if let Err(e) = self.before_send(&mut msg, data.as_ref()) {
+                                    let _ = resp.send(Err(e));
+                                    continue;
+                                }
+                                let msg_id = msg.message_id().to_string();
+                                #[cfg(feature = "tracing")]
+                                span.record("message_id", msg_id.as_str());
+                                if self.responders.contains(&msg_id) {
+                                    let _ = resp.send(Err(Error::DuplicateMessageId(msg_id)));
+                                    continue;
+                                }
+                                let text = msg.to_token()?.serialize()?;
+                                let resend = if msg.is_idempotent() { Some(text.clone()) } else { None };
+                                if let Some(limiter) = &mut self.limiter {
+                                    limiter.take(data.as_ref().map_or(0, Payload::len), queued.elapsed());
+                                }
+                                self.responders.insert(msg_id, Pending {
+                                    resp,
+                                    resend,
+                                    deadline,
+                                    expects_binary,
+                                    sent: tokio::time::Instant::now(),
+                                    #[cfg(feature = "tracing")]
+                                    span,
+                                });
+                                if let Err(e) = Self::send_message(&mut sink, text, data).await {
+                                    return Ok(Disconnect::Remote(Err(e)));
+                                }
+                            }
+                        }
+                    },
+                    _ = wakeup.fuse() => {
+                        self.responders.expire(tokio::time::Instant::now());
+                    },
+                    _ = throttle_wakeup.fuse() => {},
+                    _ = ping.fuse() => {
+                        match keepalive.poll(tokio::time::Instant::now()) {
+                            Err(e) => return Ok(Disconnect::Remote(Err(e))),
+                            Ok(Some(payload)) => {
+                                if let Err(e) = sink.send(WsMessage::Ping(payload.into())).await {
+                                    return Ok(Disconnect::Remote(Err(e.into())));
+                                }
+                            }
+                            Ok(None) => {}
+                        }
+                    },
+                    closed = &mut self.closer => {
+                        // The sender is dropped along with the `Client`, which only closes the
+                        // connection once the last `ClientHandle` is gone as well.
+                        if let Ok(close) = closed {
+                            let abandoned = self.shutdown(sink, stream, close.drain_until).await?;
+                            let _ = close.abandoned.send(abandoned);
+                            return Ok(Disconnect::Local);
+                        }
+                    }
+                }
         }
     }
 
-    pub async fn call(&self, msg: Message, data: Option<Vec<u8>>) -> Result<Response> {
-        if msg.has_binary() != data.is_some() {
-            return Err(Error::BinaryMismatch);
+    /// Closes the command queues and fails the commands still in them, returning how many there were.
+    fn stop_accepting(&mut self) -> usize {
+        let mut abandoned = 0;
+        if let Some(Command { resp, .. }) = self.held.take() {
+            let _ = resp.send(Err(Error::Closed));
+            abandoned += 1;
         }
-        if self.tx.is_closed() {
-            return Err(Error::Closed);
+        let (interactive, bulk) = self.rx.get_mut().get_mut();
+        for rx in [interactive.as_mut(), bulk.as_mut()] {
+            rx.close();
+            while let Ok(Command { resp, .. }) = rx.try_recv() {
+                let _ = resp.send(Err(Error::Closed));
+                abandoned += 1;
+            }
         }
-        let (resp, rx) = tokio::sync::oneshot::channel();
-        self.tx.send(Command { msg, resp, data }).await?;
-        Ok(rx.await?)
+        abandoned
     }
 
-    pub fn blocking_call(&self, msg: Message, data: Option<Vec<u8>>) -> Result<Response> {
-        if msg.has_binary() != data.is_some() {
-            return Err(Error::BinaryMismatch);
+    /// Closes the connection, first waiting until `drain_until` for pending requests to be answered.
+    /// Returns the number of requests that were abandoned.
+    async fn shutdown(&mut self, sink: SplitSink<WebSocket, WsMessage>, mut stream: futures_util::stream::Fuse<futures_util::stream::SplitStream<WebSocket>>, drain_until: Option<tokio::time::Instant>) -> Result<usize> {
+        let mut abandoned = self.stop_accepting();
+        let mut open = true;
+        if let Some(drain_until) = drain_until {
+            let mut deadline = Box::pin(tokio::time::sleep_until(drain_until)).fuse();
+            while open && !self.responders.is_empty() {
+                let wakeup = self.responders.wakeup();
+                select! {
+                    msg = stream.next() => {
+                        match msg {
+                            Some(Ok(WsMessage::Text(text))) => self.dispatch(text.as_str()),
+                            Some(Ok(WsMessage::Binary(data))) => self.dispatch_binary(data),
+                            Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => open = false,
+                            _ => (),
+                        }
+                    },
+                    _ = wakeup.fuse() => {
+                        self.responders.expire(tokio::time::Instant::now());
+                    },
+                    _ = deadline => break,
+                }
+            }
         }
-        if self.tx.is_closed() {
-            return Err(Error::Closed);
+        abandoned += self.responders.len();
+        self.responders.fail_all(|| Error::Closed);
+        #[cfg(feature = "tracing")]
+        tracing::info!(abandoned, "closing");
+        if open {
+            let mut websocket = sink.reunite(stream.into_inner()).unwrap();
+            websocket.close(None).await?;
+            if let Some(drain_until) = drain_until {
+                // Wait for the server to acknowledge the close.
+                let _ = tokio::time::timeout_at(drain_until, async {
+                    while let Some(Ok(_)) = websocket.next().await {}
+                }).await;
+            }
         }
-        let (resp, rx) = tokio::sync::oneshot::channel();
-        self.tx.blocking_send(Command { msg, resp, data })?;
-        Ok(rx.blocking_recv()?)
+        Ok(abandoned)
     }
-}
 
-impl Drop for Client {
-    fn drop(&mut self) {
-        if let Some(close_tx) = self.close_tx.take() {
-            let _ = close_tx.send(());
+    fn dispatch(&mut self, text: &str) {
+        if let Some(AwaitingBinary { response, copy }) = self.awaiting_binary.take() {
+            match self.responders.remove(response.message_id()) {
+                Some(pending) => {
+                    let _ = pending.resp.send(Err(Error::MissingBinary));
+                    if let Some(copy) = copy {
+                        let _ = self.incoming.send(Incoming { response: copy, data: None, matched: true });
+                    }
+                }
+                None => {
+                    let _ = self.incoming.send(Incoming { response: Arc::new(response), data: None, matched: false });
+                }
+            }
         }
-        match self.handle.take() {
-            Some(Handle::Sync(handle)) => {
-                handle.join().unwrap().expect("Error during drop");
+        match Response::deserialize(text) {
+            Ok(response) => {
+                let copy = self.copy_matched(text, &response);
+                if self.responders.expects_binary(response.message_id()) {
+                    self.awaiting_binary = Some(AwaitingBinary { response, copy });
+                } else {
+                    self.complete(response, copy, None);
+                }
             }
-            Some(Handle::Tokio(handle, rt)) => {
-                panic!("Dropping async client without closing");
+            Err(error) => {
+                match Response::peek_message_id(text).and_then(|msg_id| self.responders.remove(&msg_id)) {
+                    Some(pending) => {
+                        let _ = pending.resp.send(Err(Error::Decode { error, text: text.to_string() }));
+                    }
+                    None => {
+                        let _ = self.diagnostics.send(Diagnostic::UndecodableFrame { error: Arc::new(error), text: text.to_string() });
+                    }
+                }
             }
-            None => {}
         }
     }
-}
+
+    fn dispatch_binary(&mut self, data: Bytes) {
+        match self.awaiting_binary.take() {
+            Some(AwaitingBinary { response, copy }) => self.complete(response, copy, Some(data)),
+            None => {
+                let _ = self.diagnostics.send(Diagnostic::UnexpectedBinary(data));
+            }
+        }
+    }
+
+    /// Runs the interceptors and checks that the payload still fits the message they may have changed.
+    fn before_send(&mut self, msg: &mut Message, data: Option<&Payload>) -> Result<()> {
+        for interceptor in &mut self.interceptors {
+            interceptor.before_send(msg, data)?;
+        }
+        Payload::validate(msg, data)
+    }
+
+    /// Hands a response to the call it answers, or to subscribers if there is none.
+    fn complete(&mut self, response: Response, copy: Option<Arc<Response>>, data: Option<Bytes>) {
+        match self.responders.remove(response.message_id()) {
+            Some(pending) => {
+                let latency = pending.sent.elapsed();
+                #[cfg(feature = "tracing")]
+                pending.span.record("latency_ms", latency.as_secs_f64() * 1000.0);
+                for interceptor in self.interceptors.iter_mut().rev() {
+                    interceptor.after_receive(&response, latency);
+                }
+                if let Some(copy) = copy {
+                    let _ = self.incoming.send(Incoming { response: copy, data: data.clone(), matched: true });
+                }
+                let _ = pending.resp.send(Ok((response, data)));
+            }
+            None => {
+                let _ = self.incoming.send(Incoming { response: Arc::new(response), data, matched: false });
+            }
+        }
+    }
+
+    /// `Response` can't be cloned, so subscribers that want matched responses get a second decode of the frame.
+    fn copy_matched(&self, text: &str, response: &Response) -> Option<Arc<Response>> {
+        if !self.options.copy_matched || self.incoming.receiver_count() == 0 || !self.responders.contains(response.message_id()) {
+            return None;
+        }
+        Response::deserialize(text).ok().map(Arc::new)
+    }
+
+    async fn send_message(sink: &mut SplitSink<WebSocket, WsMessage>, text: String, data: Option<Payload>) -> Result<()> {
+        sink.send(WsMessage::text(text)).await?;
+        match data {
+            Some(Payload::Bytes(binary)) => sink.send(WsMessage::Binary(binary)).await?,
+            Some(Payload::Reader { mut reader, len }) => {
+                // Sent as a fragmented message so the whole payload never has to be in memory.
+                let mut remaining = len;
+                let mut opcode = OpCode::Data(Data::Binary);
+                loop {
+                    let mut chunk = vec![0; remaining.min(Payload::CHUNK_SIZE)];
+                    reader.read_exact(&mut chunk).await?;
+                    remaining -= chunk.len();
+                    sink.feed(WsMessage::Frame(Frame::message(chunk, opcode, remaining == 0))).await?;
+                    if remaining == 0 {
+                        break;
+                    }
+                    opcode = OpCode::Data(Data::Continue);
+                }
+            }
+            None => {}
+        }
+        sink.flush().await?;
+        Ok(())
+    }
+}
+
+impl Client {
+    fn connect_impl(task: ClientTask) -> Result<(std::thread::JoinHandle<Result<()>>,tokio::sync::oneshot::Receiver<()>)> {
+        let (resp_tx, resp_rx) = tokio::sync::oneshot::channel();
+
+        let handle = std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+            runtime.block_on(task.run(resp_tx))
+        });
+        Ok((handle, resp_rx))
+    }
+
+    fn connect_async(task: ClientTask, runtime: &tokio::runtime::Handle) -> Result<(tokio::task::JoinHandle<Result<()>>,tokio::sync::oneshot::Receiver<()>)> {
+        let (resp_tx, resp_rx) = tokio::sync::oneshot::channel();
+
+        let handle = runtime.spawn(task.run(resp_tx));
+
+        Ok((handle, resp_rx))
+    }
+
+    pub fn builder<R: IntoClientRequest>(request: R) -> Result<ClientBuilder> {
+        ClientBuilder::new(request)
+    }
+
+    pub async fn connect<R: IntoClientRequest>(request: R) -> Result<Client> {
+        ClientBuilder::new(request)?.connect().await
+    }
+
+    pub fn blocking_connect<R: IntoClientRequest>(request: R) -> Result<Client> {
+        ClientBuilder::new(request)?.blocking_connect()
+    }
+
+    /// Connects with the connection task spawned onto `runtime`.
+    pub async fn connect_on<R: IntoClientRequest>(request: R, runtime: tokio::runtime::Handle) -> Result<Client> {
+        ClientBuilder::new(request)?.runtime(runtime).connect().await
+    }
+
+    pub fn blocking_connect_on<R: IntoClientRequest>(request: R, runtime: tokio::runtime::Handle) -> Result<Client> {
+        ClientBuilder::new(request)?.runtime(runtime).blocking_connect()
+    }
+
+    pub async fn connect_port(port: u16) -> Result<Client> {
+        ClientBuilder::port(port)?.connect().await
+    }
+
+    pub fn blocking_connect_port(port: u16) -> Result<Client> {
+        ClientBuilder::port(port)?.blocking_connect()
+    }
+
+    fn send_close(&mut self, drain: Option<Duration>) -> tokio::sync::oneshot::Receiver<usize> {
+        // Stands in for a timeout too long to represent, which waits as long as it takes.
+        const FOREVER: Duration = Duration::from_secs(30 * 365 * 86400);
+        let drain_until = drain.map(|timeout| {
+            let now = tokio::time::Instant::now();
+            now.checked_add(timeout).unwrap_or(now + FOREVER)
+        });
+        let (abandoned, abandoned_rx) = tokio::sync::oneshot::channel();
+        if let Some(close_tx) = self.close_tx.take() {
+            let _ = close_tx.send(CloseRequest { drain_until, abandoned });
+        }
+        abandoned_rx
+    }
+
+    /// Closes the connection right away. Requests still waiting for a response fail with `Error::Closed`.
+    pub async fn close(mut self) -> Result<()> {
+        self.send_close(None);
+        self.join().await
+    }
+
+    /// Stops accepting new requests and waits up to `timeout` for pending ones to be answered before
+    /// closing the connection. Returns the number of requests that were abandoned.
+    pub async fn close_graceful(mut self, timeout: Duration) -> Result<usize> {
+        let abandoned = self.send_close(Some(timeout));
+        self.join().await?;
+        Ok(abandoned.await.unwrap_or(0))
+    }
+
+    async fn join(&mut self) -> Result<()> {
+        self.handle.tx.closed().await;
+        match self.task.take() {
+            Some(Handle::Sync(handle)) => {
+                handle.join().unwrap()?;
+            }
+            Some(Handle::Tokio(handle, _)) => {
+                handle.await.unwrap()?;
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    pub fn blocking_close(mut self) -> Result<()> {
+        self.send_close(None);
+        self.blocking_join()
+    }
+
+    pub fn blocking_close_graceful(mut self, timeout: Duration) -> Result<usize> {
+        let abandoned = self.send_close(Some(timeout));
+        self.blocking_join()?;
+        Ok(abandoned.blocking_recv().unwrap_or(0))
+    }
+
+    fn blocking_join(&mut self) -> Result<()> {
+        match self.task.take() {
+            Some(Handle::Sync(handle)) => {
+                handle.join().unwrap()?;
+            }
+            Some(Handle::Tokio(handle, rt)) => {
+                rt.block_on(async move {
+                    handle.await.unwrap()
+                })?;
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    pub fn is_active(&self) -> bool {
+        match &self.task {
+            Some(Handle::Sync(handle)) => !handle.is_finished(),
+            Some(Handle::Tokio(handle, _)) => !handle.is_finished(),
+            None => false,
+        }
+    }
+
+    pub fn handle(&self) -> ClientHandle {
+        self.handle.clone()
+    }
+}
+
+impl std::ops::Deref for Client {
+    type Target = ClientHandle;
+
+    fn deref(&self) -> &ClientHandle {
+        &self.handle
+    }
+}
+
+impl ClientHandle {
+    pub fn is_active(&self) -> bool {
+        !self.tx.is_closed()
+    }
+
+    /// The error that terminated the connection, or that interrupted it while it is reconnecting.
+    pub fn last_error(&self) -> Option<Arc<Error>> {
+        self.last_error.lock().unwrap().clone()
+    }
+
+    /// Round trip time measured by the most recent keepalive ping.
+    pub fn round_trip_time(&self) -> Option<Duration> {
+        *self.round_trip_time.lock().unwrap()
+    }
+
+    /// Session data fetched when the connection was (re-)established. `None` if the server didn't
+    /// provide it in time or `ClientBuilder::session_info` turned it off, use `get_session_data` to ask again.
+    pub fn session_info(&self) -> Option<Arc<SessionData>> {
+        self.session.lock().unwrap().clone()
+    }
+
+    /// A handle whose calls go through the given lane, see `Priority`.
+    pub fn with_priority(&self, priority: Priority) -> ClientHandle {
+        ClientHandle { priority, ..self.clone() }
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    fn sender(&self) -> &tokio::sync::mpsc::Sender<Command> {
+        match self.priority {
+            Priority::Interactive => &self.tx,
+            Priority::Bulk => &self.bulk_tx,
+        }
+    }
+
+    pub fn limiter_stats(&self) -> LimiterStats {
+        *self.limiter_stats.lock().unwrap()
+    }
+
+    fn closed_error(&self) -> Error {
+        match self.last_error() {
+            Some(e) if self.tx.is_closed() => Error::Connection(e),
+            _ => Error::Closed,
+        }
+    }
+
+    pub fn connection_state(&self) -> ConnectionState {
+        *self.state.borrow()
+    }
+
+    pub fn watch_connection_state(&self) -> tokio_stream::wrappers::WatchStream<ConnectionState> {
+        tokio_stream::wrappers::WatchStream::new(self.state.clone())
+    }
+
+    pub fn diagnostics(&self) -> tokio_stream::wrappers::BroadcastStream<Diagnostic> {
+        tokio_stream::wrappers::BroadcastStream::new(self.diagnostics.subscribe())
+    }
+
+    /// Responses that didn't answer any call, such as messages initiated by the server. With
+    /// `ClientBuilder::copy_matched_responses` every other response is included as well.
+    pub fn subscribe(&self) -> tokio_stream::wrappers::BroadcastStream<Incoming> {
+        tokio_stream::wrappers::BroadcastStream::new(self.incoming.subscribe())
+    }
+
+    pub async fn call(&self, msg: Message, data: Option<Payload>) -> Result<Response> {
+        Ok(self.call_impl(msg, data, self.default_timeout, false).await?.0)
+    }
+
+    pub async fn call_with_timeout(&self, msg: Message, data: Option<Payload>, timeout: Duration) -> Result<Response> {
+        Ok(self.call_impl(msg, data, Some(timeout), false).await?.0)
+    }
+
+    /// Like `call`, but the response must be followed by a binary frame, which is returned with it.
+    /// Fails with `Error::MissingBinary` if another text frame arrives first.
+    pub async fn call_with_binary(&self, msg: Message, data: Option<Payload>) -> Result<(Response, Option<Bytes>)> {
+        self.call_impl(msg, data, self.default_timeout, true).await
+    }
+
+    async fn call_impl(&self, msg: Message, data: Option<Payload>, timeout: Option<Duration>, expects_binary: bool) -> Result<(Response, Option<Bytes>)> {
+        #[cfg(feature = "tracing")]
+        let span = Self::call_span(&msg, data.as_ref());
+        let call = async {
+            Payload::validate(&msg, data.as_ref())?;
+            if self.tx.is_closed() {
+                return Err(self.closed_error());
+            }
+            // A timeout too long to represent can't expire.
+            let deadline = timeout.and_then(|timeout| tokio::time::Instant::now().checked_add(timeout));
+            let (resp, rx) = tokio::sync::oneshot::channel();
+            let call = async {
+                let command = Command {
+                    msg,
+                    resp,
+                    data,
+                    deadline,
+                    queued: tokio::time::Instant::now(),
+                    expects_binary,
+                    #[cfg(feature = "tracing")]
+                    span: span.clone(),
+                };
+                if self.sender().send(command).await.is_err() {
+                    return Err(self.closed_error());
+                }
+                rx.await.unwrap_or_else(|_| Err(self.closed_error()))
+            };
+            match deadline {
+                // The connection task enforces the deadline as well, this only covers waiting for the queue.
+                Some(deadline) if tokio::runtime::Handle::try_current().is_ok() => {
+                    tokio::time::timeout_at(deadline, call).await.unwrap_or(Err(Error::Timeout))
+                }
+                _ => call.await,
+            }
+        };
+        #[cfg(feature = "tracing")]
+        let call = tracing::Instrument::instrument(call, span.clone());
+        let result = call.await;
+        #[cfg(feature = "tracing")]
+        Self::record_outcome(&span, &result);
+        result
+    }
+
+    #[cfg(feature = "tracing")]
+    fn call_span(msg: &Message, data: Option<&Payload>) -> tracing::Span {
+        tracing::info_span!(
+            "call",
+            kind = msg.kind(),
+            message_id = tracing::field::Empty,
+            payload_size = data.map_or(0, Payload::len),
+            latency_ms = tracing::field::Empty,
+            outcome = tracing::field::Empty,
+        )
+    }
+
+    #[cfg(feature = "tracing")]
+    fn record_outcome<T>(span: &tracing::Span, result: &Result<T>) {
+        match result {
+            Ok(_) => {
+                span.record("outcome", "ok");
+            }
+            Err(e) => {
+                span.record("outcome", tracing::field::display(e));
+            }
+        }
+    }
+
+    pub fn blocking_call(&self, msg: Message, data: Option<Payload>) -> Result<Response> {
+        Ok(self.blocking_call_impl(msg, data, self.default_timeout, false)?.0)
+    }
+
+    pub fn blocking_call_with_timeout(&self, msg: Message, data: Option<Payload>, timeout: Duration) -> Result<Response> {
+        Ok(self.blocking_call_impl(msg, data, Some(timeout), false)?.0)
+    }
+
+    pub fn blocking_call_with_binary(&self, msg: Message, data: Option<Payload>) -> Result<(Response, Option<Bytes>)> {
+        self.blocking_call_impl(msg, data, self.default_timeout, true)
+    }
+
+    fn blocking_call_impl(&self, msg: Message, data: Option<Payload>, timeout: Option<Duration>, expects_binary: bool) -> Result<(Response, Option<Bytes>)> {
+        #[cfg(feature = "tracing")]
+        let span = Self::call_span(&msg, data.as_ref());
+        #[cfg(feature = "tracing")]
+        let _entered = span.enter();
+        let call = || {
+            Payload::validate(&msg, data.as_ref())?;
+            if self.tx.is_closed() {
+                return Err(self.closed_error());
+            }
+            // A timeout too long to represent can't expire.
+            let deadline = timeout.and_then(|timeout| tokio::time::Instant::now().checked_add(timeout));
+            let (resp, rx) = tokio::sync::oneshot::channel();
+            let command = Command {
+                msg,
+                resp,
+                data,
+                deadline,
+                queued: tokio::time::Instant::now(),
+                expects_binary,
+                #[cfg(feature = "tracing")]
+                span: span.clone(),
+            };
+            let call = async {
+                if self.sender().send(command).await.is_err() {
+                    return Err(self.closed_error());
+                }
+                rx.await.unwrap_or_else(|_| Err(self.closed_error()))
+            };
+            // The connection task only checks the deadline once it takes the command, which can be
+            // much later while the queue is full or the rate limit holds commands back.
+            block_on_until(call, deadline.map(tokio::time::Instant::into_std)).unwrap_or(Err(Error::Timeout))
+        };
+        let result = call();
+        #[cfg(feature = "tracing")]
+        Self::record_outcome(&span, &result);
+        result
+    }
+}
+
+impl ClientHandle {
+    fn typed_response<R: Request>(kind: &'static str, response: Response) -> Result<R::Response> {
+        let response = response.into_result().map_err(|e| e.with_message_kind(kind))?;
+        R::Response::try_from(response).map_err(|response| Error::UnexpectedResponse(Box::new(response)))
+    }
+
+    pub async fn request<R: Request>(&self, request: R, data: Option<Payload>) -> Result<R::Response> {
+        let msg: Message = request.into();
+        let kind = msg.kind();
+        Self::typed_response::<R>(kind, self.call(msg, data).await?)
+    }
+
+    pub fn blocking_request<R: Request>(&self, request: R, data: Option<Payload>) -> Result<R::Response> {
+        let msg: Message = request.into();
+        let kind = msg.kind();
+        Self::typed_response::<R>(kind, self.blocking_call(msg, data)?)
+    }
+
+    pub async fn get_slot(&self, slot_id: impl Into<String>, depth: i32, include_component_data: bool) -> Result<SlotData> {
+        self.request(GetSlot { message_id: Default::default(), slot_id: slot_id.into(), depth, include_component_data }, None).await
+    }
+
+    pub fn blocking_get_slot(&self, slot_id: impl Into<String>, depth: i32, include_component_data: bool) -> Result<SlotData> {
+        self.blocking_request(GetSlot { message_id: Default::default(), slot_id: slot_id.into(), depth, include_component_data }, None)
+    }
+
+    pub async fn add_slot(&self, data: AddSlotData) -> Result<ResponseData> {
+        self.request(AddSlot { message_id: Default::default(), data }, None).await
+    }
+
+    pub fn blocking_add_slot(&self, data: AddSlotData) -> Result<ResponseData> {
+        self.blocking_request(AddSlot { message_id: Default::default(), data }, None)
+    }
+
+    pub async fn update_slot(&self, data: UpdateSlotData) -> Result<ResponseData> {
+        self.request(UpdateSlot { message_id: Default::default(), data }, None).await
+    }
+
+    pub fn blocking_update_slot(&self, data: UpdateSlotData) -> Result<ResponseData> {
+        self.blocking_request(UpdateSlot { message_id: Default::default(), data }, None)
+    }
+
+    pub async fn remove_slot(&self, slot_id: impl Into<String>) -> Result<ResponseData> {
+        self.request(RemoveSlot { message_id: Default::default(), slot_id: slot_id.into() }, None).await
+    }
+
+    pub fn blocking_remove_slot(&self, slot_id: impl Into<String>) -> Result<ResponseData> {
+        self.blocking_request(RemoveSlot { message_id: Default::default(), slot_id: slot_id.into() }, None)
+    }
+
+    pub async fn get_component(&self, component_id: impl Into<String>) -> Result<ComponentData> {
+        self.request(GetComponent { message_id: Default::default(), component_id: component_id.into() }, None).await
+    }
+
+    pub fn blocking_get_component(&self, component_id: impl Into<String>) -> Result<ComponentData> {
+        self.blocking_request(GetComponent { message_id: Default::default(), component_id: component_id.into() }, None)
+    }
+
+    pub async fn add_component(&self, container_slot_id: impl Into<String>, data: AddComponentData) -> Result<ResponseData> {
+        self.request(AddComponent { message_id: Default::default(), container_slot_id: container_slot_id.into(), data }, None).await
+    }
+
+    pub fn blocking_add_component(&self, container_slot_id: impl Into<String>, data: AddComponentData) -> Result<ResponseData> {
+        self.blocking_request(AddComponent { message_id: Default::default(), container_slot_id: container_slot_id.into(), data }, None)
+    }
+
+    pub async fn update_component(&self, component_id: impl Into<String>, members: HashMap<String, Member>) -> Result<ResponseData> {
+        self.request(UpdateComponent { message_id: Default::default(), data: UpdateComponentData { id: component_id.into(), members } }, None).await
+    }
+
+    pub fn blocking_update_component(&self, component_id: impl Into<String>, members: HashMap<String, Member>) -> Result<ResponseData> {
+        self.blocking_request(UpdateComponent { message_id: Default::default(), data: UpdateComponentData { id: component_id.into(), members } }, None)
+    }
+
+    pub async fn remove_component(&self, component_id: impl Into<String>) -> Result<ResponseData> {
+        self.request(RemoveComponent { message_id: Default::default(), component_id: component_id.into() }, None).await
+    }
+
+    pub fn blocking_remove_component(&self, component_id: impl Into<String>) -> Result<ResponseData> {
+        self.blocking_request(RemoveComponent { message_id: Default::default(), component_id: component_id.into() }, None)
+    }
+
+    pub async fn get_session_data(&self) -> Result<SessionData> {
+        self.request(RequestSessionData { message_id: Default::default() }, None).await
+    }
+
+    pub fn blocking_get_session_data(&self) -> Result<SessionData> {
+        self.blocking_request(RequestSessionData { message_id: Default::default() }, None)
+    }
+
+    pub async fn get_component_definition(&self, component_type: impl Into<String>) -> Result<ComponentDefinitionData> {
+        self.request(GetComponentDefinition { message_id: Default::default(), component_type: component_type.into() }, None).await
+    }
+
+    pub fn blocking_get_component_definition(&self, component_type: impl Into<String>) -> Result<ComponentDefinitionData> {
+        self.blocking_request(GetComponentDefinition { message_id: Default::default(), component_type: component_type.into() }, None)
+    }
+
+    pub async fn get_enum_definition(&self, enum_type: impl Into<String>) -> Result<EnumDefinitionData> {
+        self.request(GetEnumDefinition { message_id: Default::default(), enum_type: enum_type.into() }, None).await
+    }
+
+    pub fn blocking_get_enum_definition(&self, enum_type: impl Into<String>) -> Result<EnumDefinitionData> {
+        self.blocking_request(GetEnumDefinition { message_id: Default::default(), enum_type: enum_type.into() }, None)
+    }
+
+    /// The definition of `component_type`, fetched once and then cached for every handle of the client.
+    pub async fn component_definition(&self, component_type: &str) -> Result<Arc<ComponentDefinition>> {
+        if let Some(definition) = self.cached_definition(component_type) {
+            return Ok(definition);
+        }
+        let data = self.get_component_definition(component_type).await?;
+        Ok(self.cache_definition(component_type, data.definition))
+    }
+
+    pub fn blocking_component_definition(&self, component_type: &str) -> Result<Arc<ComponentDefinition>> {
+        if let Some(definition) = self.cached_definition(component_type) {
+            return Ok(definition);
+        }
+        let data = self.blocking_get_component_definition(component_type)?;
+        Ok(self.cache_definition(component_type, data.definition))
+    }
+
+    fn cached_definition(&self, component_type: &str) -> Option<Arc<ComponentDefinition>> {
+        self.definitions.lock().unwrap().get(component_type).cloned()
+    }
+
+    fn cache_definition(&self, component_type: &str, definition: ComponentDefinition) -> Arc<ComponentDefinition> {
+        let definition = Arc::new(definition);
+        self.definitions.lock().unwrap().insert(component_type.to_string(), definition.clone());
+        definition
+    }
+
+    pub fn clear_definition_cache(&self) {
+        self.definitions.lock().unwrap().clear();
+    }
+
+    /// Checks `members` against the (cached) definition of `component_type` before they are sent.
+    pub async fn validate_members(&self, component_type: &str, members: &HashMap<String, Member>) -> Result<()> {
+        Ok(self.component_definition(component_type).await?.validate(members)?)
+    }
+
+    pub fn blocking_validate_members(&self, component_type: &str, members: &HashMap<String, Member>) -> Result<()> {
+        Ok(self.blocking_component_definition(component_type)?.validate(members)?)
+    }
+
+    pub async fn import_texture_2d_file(&self, file_path: impl Into<String>) -> Result<ResponseData> {
+        self.request(ImportTexture2DFile { message_id: Default::default(), file_path: file_path.into() }, None).await
+    }
+
+    pub fn blocking_import_texture_2d_file(&self, file_path: impl Into<String>) -> Result<ResponseData> {
+        self.blocking_request(ImportTexture2DFile { message_id: Default::default(), file_path: file_path.into() }, None)
+    }
+
+    pub async fn import_texture_2d_raw_data(&self, width: i32, height: i32, color_profile: impl Into<String>, data: impl Into<Payload>) -> Result<ResponseData> {
+        self.request(ImportTexture2DRawData { message_id: Default::default(), width, height, color_profile: color_profile.into() }, Some(data.into())).await
+    }
+
+    pub fn blocking_import_texture_2d_raw_data(&self, width: i32, height: i32, color_profile: impl Into<String>, data: impl Into<Payload>) -> Result<ResponseData> {
+        self.blocking_request(ImportTexture2DRawData { message_id: Default::default(), width, height, color_profile: color_profile.into() }, Some(data.into()))
+    }
+
+    pub async fn import_texture_2d_raw_data_hdr(&self, width: i32, height: i32, data: impl Into<Payload>) -> Result<ResponseData> {
+        self.request(ImportTexture2DRawDataHDR { message_id: Default::default(), width, height }, Some(data.into())).await
+    }
+
+    pub fn blocking_import_texture_2d_raw_data_hdr(&self, width: i32, height: i32, data: impl Into<Payload>) -> Result<ResponseData> {
+        self.blocking_request(ImportTexture2DRawDataHDR { message_id: Default::default(), width, height }, Some(data.into()))
+    }
+
+    pub async fn import_mesh_file(&self, file_path: impl Into<String>) -> Result<AssetData> {
+        self.request(ImportMeshFile { message_id: Default::default(), file_path: file_path.into() }, None).await
+    }
+
+    pub fn blocking_import_mesh_file(&self, file_path: impl Into<String>) -> Result<AssetData> {
+        self.blocking_request(ImportMeshFile { message_id: Default::default(), file_path: file_path.into() }, None)
+    }
+
+    /// Encodes `mesh` and imports it with `ImportMeshRawData`.
+    pub async fn import_mesh(&self, mesh: &MeshData) -> Result<AssetData> {
+        let (msg, data) = mesh.encode()?;
+        self.request(msg, Some(data.into())).await
+    }
+
+    pub fn blocking_import_mesh(&self, mesh: &MeshData) -> Result<AssetData> {
+        let (msg, data) = mesh.encode()?;
+        self.blocking_request(msg, Some(data.into()))
+    }
+
+    pub async fn import_audio_clip_file(&self, file_path: impl Into<String>) -> Result<AssetData> {
+        self.request(ImportAudioClipFile { message_id: Default::default(), file_path: file_path.into() }, None).await
+    }
+
+    pub fn blocking_import_audio_clip_file(&self, file_path: impl Into<String>) -> Result<AssetData> {
+        self.blocking_request(ImportAudioClipFile { message_id: Default::default(), file_path: file_path.into() }, None)
+    }
+
+    /// Imports interleaved PCM samples as an audio clip, see `audio::encode_samples`.
+    pub async fn import_audio_clip(&self, samples: &[f32], sample_rate: u32, channel_count: u16) -> Result<AssetData> {
+        let (msg, data) = crate::audio::encode_samples(samples, sample_rate, channel_count)?;
+        self.request(msg, Some(data.into())).await
+    }
+
+    pub fn blocking_import_audio_clip(&self, samples: &[f32], sample_rate: u32, channel_count: u16) -> Result<AssetData> {
+        let (msg, data) = crate::audio::encode_samples(samples, sample_rate, channel_count)?;
+        self.blocking_request(msg, Some(data.into()))
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        match self.task.take() {
+            // Without other handles nothing would be left to end the thread, so close and wait for it.
+            Some(Handle::Sync(handle)) if self.handle.tx.strong_count() == 1 => {
+                self.send_close(None);
+                let _ = handle.join();
+            }
+            _ => {}
+        }
+    }
+}
 
 struct Command {
     msg: Message,
-    resp: tokio::sync::oneshot::Sender<Response>,
-    data: Option<Vec<u8>>,
-}
\ No newline at end of file
+    resp: tokio::sync::oneshot::Sender<Result<(Response, Option<Bytes>)>>,
+    data: Option<Payload>,
+    deadline: Option<tokio::time::Instant>,
+    queued: tokio::time::Instant,
+    /// The response is followed by a binary frame, see `ClientHandle::call_with_binary`.
+    expects_binary: bool,
+    /// Span of the call, filled in with the message id and latency by the connection task.
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+struct CloseRequest {
+    /// Pending requests get until then to be answered, `None` closes immediately.
+    drain_until: Option<tokio::time::Instant>,
+    abandoned: tokio::sync::oneshot::Sender<usize>,
+}
+
+struct Pending {
+    resp: tokio::sync::oneshot::Sender<Result<(Response, Option<Bytes>)>>,
+    resend: Option<String>,
+    deadline: Option<tokio::time::Instant>,
+    expects_binary: bool,
+    sent: tokio::time::Instant,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+/// Callers waiting for a response, keyed by message id.
+struct Responders {
+    pending: HashMap<String, Pending>,
+    deadlines: BinaryHeap<Reverse<(tokio::time::Instant, String)>>,
+    next_sweep: tokio::time::Instant,
+}
+
+impl Responders {
+    /// How often entries whose caller stopped waiting are removed.
+    const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+    fn new() -> Self {
+        Self {
+            pending: HashMap::new(),
+            deadlines: BinaryHeap::new(),
+            next_sweep: tokio::time::Instant::now() + Self::SWEEP_INTERVAL,
+        }
+    }
+
+    fn insert(&mut self, msg_id: String, pending: Pending) {
+        if let Some(deadline) = pending.deadline {
+            self.deadlines.push(Reverse((deadline, msg_id.clone())));
+        }
+        self.pending.insert(msg_id, pending);
+    }
+
+    fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    fn contains(&self, msg_id: &str) -> bool {
+        self.pending.contains_key(msg_id)
+    }
+
+    fn expects_binary(&self, msg_id: &str) -> bool {
+        self.pending.get(msg_id).is_some_and(|pending| pending.expects_binary)
+    }
+
+    fn remove(&mut self, msg_id: &str) -> Option<Pending> {
+        self.pending.remove(msg_id)
+    }
+
+    /// Fails everything that can't be replayed on a new connection and returns what can.
+    fn take_replay(&mut self, policy: PendingPolicy) -> Vec<String> {
+        let mut replay = vec![];
+        for (msg_id, pending) in std::mem::take(&mut self.pending) {
+            match (policy, &pending.resend) {
+                (PendingPolicy::ResendIdempotent, Some(text)) => {
+                    replay.push(text.clone());
+                    self.pending.insert(msg_id, pending);
+                }
+                _ => {
+                    let _ = pending.resp.send(Err(Error::Reconnected));
+                }
+            }
+        }
+        replay
+    }
+
+    fn fail_all(&mut self, error: impl Fn() -> Error) {
+        for (_, pending) in self.pending.drain() {
+            let _ = pending.resp.send(Err(error()));
+        }
+        self.deadlines.clear();
+    }
+
+    fn wakeup(&self) -> impl Future<Output = ()> + use<> {
+        let wakeup = if self.pending.is_empty() {
+            None
+        } else {
+            Some(match self.deadlines.peek() {
+                Some(Reverse((deadline, _))) => (*deadline).min(self.next_sweep),
+                None => self.next_sweep,
+            })
+        };
+        async move {
+            match wakeup {
+                Some(wakeup) => tokio::time::sleep_until(wakeup).await,
+                None => std::future::pending().await,
+            }
+        }
+    }
+
+    fn expire(&mut self, now: tokio::time::Instant) {
+        while self.deadlines.peek().is_some_and(|Reverse((deadline, _))| *deadline <= now) {
+            let Reverse((deadline, msg_id)) = self.deadlines.pop().unwrap();
+            if self.pending.get(&msg_id).is_some_and(|pending| pending.deadline == Some(deadline)) {
+                let pending = self.pending.remove(&msg_id).unwrap();
+                let _ = pending.resp.send(Err(Error::Timeout));
+            }
+        }
+        if now >= self.next_sweep {
+            self.pending.retain(|_, pending| !pending.resp.is_closed());
+            self.next_sweep = now + Self::SWEEP_INTERVAL;
+        }
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+enum Throttle {
+    Open,
+    Until(tokio::time::Instant),
+    /// Too many requests are waiting for their response.
+    InFlight,
+}
+
+/// Token buckets enforcing a `RateLimit`.
+struct Limiter {
+    limit: RateLimit,
+    messages: f64,
+    /// Goes negative when a payload is larger than the available budget.
+    bytes: f64,
+    updated: tokio::time::Instant,
+    stats: Arc<std::sync::Mutex<LimiterStats>>,
+}
+
+impl Limiter {
+    fn new(limit: RateLimit, stats: Arc<std::sync::Mutex<LimiterStats>>, now: tokio::time::Instant) -> Self {
+        Self {
+            messages: limit.messages_per_second.unwrap_or(0.0).max(1.0),
+            bytes: limit.bytes_per_second.unwrap_or(0.0),
+            limit,
+            updated: now,
+            stats,
+        }
+    }
+
+    fn throttle(&mut self, now: tokio::time::Instant, in_flight: usize) -> Throttle {
+        if self.limit.max_in_flight.is_some_and(|max| in_flight >= max) {
+            return Throttle::InFlight;
+        }
+        let elapsed = (now - self.updated).as_secs_f64();
+        self.updated = now;
+        let mut wait = 0f64;
+        if let Some(rate) = self.limit.messages_per_second {
+            self.messages = (self.messages + elapsed * rate).min(rate.max(1.0));
+            if self.messages < 1.0 {
+                wait = wait.max((1.0 - self.messages) / rate);
+            }
+        }
+        if let Some(rate) = self.limit.bytes_per_second {
+            self.bytes = (self.bytes + elapsed * rate).min(rate);
+            if self.bytes < 0.0 {
+                wait = wait.max(-self.bytes / rate);
+            }
+        }
+        if wait > 0.0 {
+            Throttle::Until(now + Duration::from_secs_f64(wait))
+        } else {
+            Throttle::Open
+        }
+    }
+
+    fn take(&mut self, bytes: usize, waited: Duration) {
+        if self.limit.messages_per_second.is_some() {
+            self.messages -= 1.0;
+        }
+        if self.limit.bytes_per_second.is_some() {
+            self.bytes -= bytes as f64;
+        }
+        let mut stats = self.stats.lock().unwrap();
+        stats.requests += 1;
+        stats.total_wait += waited;
+        stats.max_wait = stats.max_wait.max(waited);
+    }
+}
+
+/// Keepalive state of a single connection.
+struct Keepalive {
+    policy: Option<KeepalivePolicy>,
+    next_ping: tokio::time::Instant,
+    /// Counter sent as payload of the unanswered ping and when it was sent.
+    ping: Option<(u64, tokio::time::Instant)>,
+    counter: u64,
+}
+
+impl Keepalive {
+    fn new(policy: Option<KeepalivePolicy>, now: tokio::time::Instant) -> Self {
+        let next_ping = now + policy.as_ref().map_or(Duration::ZERO, |policy| policy.interval);
+        Self { policy, next_ping, ping: None, counter: 0 }
+    }
+
+    fn wakeup(&self) -> impl Future<Output = ()> + use<> {
+        let wakeup = self.policy.as_ref().map(|policy| match self.ping {
+            Some((_, sent)) => sent + policy.timeout,
+            None => self.next_ping,
+        });
+        async move {
+            match wakeup {
+                Some(wakeup) => tokio::time::sleep_until(wakeup).await,
+                None => std::future::pending().await,
+            }
+        }
+    }
+
+    /// Returns the payload of a ping that is due, or an error if the last one wasn't answered in time.
+    fn poll(&mut self, now: tokio::time::Instant) -> Result<Option<Vec<u8>>> {
+        let Some(policy) = &self.policy else {
+            return Ok(None);
+        };
+        match self.ping {
+            Some((_, sent)) if now >= sent + policy.timeout => Err(Error::KeepaliveTimeout),
+            None if now >= self.next_ping => {
+                self.counter += 1;
+                self.ping = Some((self.counter, now));
+                Ok(Some(self.counter.to_be_bytes().to_vec()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the round trip time if `payload` answers the outstanding ping in time. A late pong
+    /// is ignored, so the next `poll` still reports the timeout.
+    fn pong(&mut self, payload: &[u8], now: tokio::time::Instant) -> Option<Duration> {
+        let policy = self.policy.as_ref()?;
+        let (counter, sent) = self.ping?;
+        if payload != counter.to_be_bytes() || now >= sent + policy.timeout {
+            return None;
+        }
+        self.ping = None;
+        self.next_ping = now + policy.interval;
+        Some(now - sent)
+    }
+}
+
+/// Polls `future` on the current thread until it completes, or gives up once `deadline` has passed.
+fn block_on_until<F: Future>(future: F, deadline: Option<std::time::Instant>) -> Option<F::Output> {
+    struct Unpark(std::thread::Thread);
+
+    impl std::task::Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = std::task::Waker::from(Arc::new(Unpark(std::thread::current())));
+    let mut cx = std::task::Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        if let std::task::Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return Some(output);
+        }
+        match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+                if remaining.is_zero() {
+                    return None;
+                }
+                std::thread::park_timeout(remaining);
+            }
+            None => std::thread::park(),
+        }
+    }
+}
+
+enum Disconnect {
+    Local,
+    Remote(Result<()>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_on_until_gives_up_at_the_deadline() {
+        let deadline = std::time::Instant::now() + Duration::from_millis(20);
+        assert!(block_on_until(std::future::pending::<()>(), Some(deadline)).is_none());
+        assert!(std::time::Instant::now() >= deadline);
+    }
+
+    #[test]
+    fn block_on_until_wakes_up_for_another_thread() {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let sender = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            tx.send(42).unwrap();
+        });
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        assert_eq!(block_on_until(rx, Some(deadline)), Some(Ok(42)));
+        assert_eq!(block_on_until(async { 1 }, None), Some(1));
+        sender.join().unwrap();
+    }
+
+    fn limiter(limit: RateLimit, now: tokio::time::Instant) -> Limiter {
+        Limiter::new(limit.clamped(), Arc::new(std::sync::Mutex::new(LimiterStats::default())), now)
+    }
+
+    #[test]
+    fn limiter_spaces_out_messages_after_a_burst() {
+        let start = tokio::time::Instant::now();
+        let mut limiter = limiter(RateLimit { messages_per_second: Some(2.0), ..Default::default() }, start);
+        for _ in 0..2 {
+            assert_eq!(limiter.throttle(start, 0), Throttle::Open);
+            limiter.take(0, Duration::ZERO);
+        }
+        assert_eq!(limiter.throttle(start, 0), Throttle::Until(start + Duration::from_millis(500)));
+        assert_eq!(limiter.throttle(start + Duration::from_millis(500), 0), Throttle::Open);
+        limiter.take(0, Duration::from_millis(500));
+        let stats = *limiter.stats.lock().unwrap();
+        assert_eq!(stats.requests, 3);
+        assert_eq!(stats.max_wait, Duration::from_millis(500));
+    }
+
+    #[test]
+    fn limiter_pays_off_large_payloads() {
+        let start = tokio::time::Instant::now();
+        let mut limiter = limiter(RateLimit { bytes_per_second: Some(100.0), ..Default::default() }, start);
+        assert_eq!(limiter.throttle(start, 0), Throttle::Open);
+        limiter.take(250, Duration::ZERO);
+        assert_eq!(limiter.throttle(start, 0), Throttle::Until(start + Duration::from_millis(1500)));
+        assert_eq!(limiter.throttle(start + Duration::from_millis(1500), 0), Throttle::Open);
+    }
+
+    #[test]
+    fn limiter_caps_requests_in_flight() {
+        let start = tokio::time::Instant::now();
+        let mut limiter = limiter(RateLimit { max_in_flight: Some(2), ..Default::default() }, start);
+        assert_eq!(limiter.throttle(start, 1), Throttle::Open);
+        assert_eq!(limiter.throttle(start, 2), Throttle::InFlight);
+    }
+
+    #[test]
+    fn rate_limit_is_clamped() {
+        let limit = RateLimit {
+            messages_per_second: Some(0.0),
+            max_in_flight: Some(0),
+            bytes_per_second: Some(f64::INFINITY),
+        }.clamped();
+        assert_eq!(limit.messages_per_second, Some(RateLimit::MIN_RATE));
+        assert_eq!(limit.max_in_flight, Some(1));
+        assert_eq!(limit.bytes_per_second, None);
+        assert_eq!(RateLimit { messages_per_second: Some(f64::NAN), ..Default::default() }.clamped().messages_per_second, None);
+
+        let start = tokio::time::Instant::now();
+        let mut limiter = limiter(RateLimit { messages_per_second: Some(-1.0), bytes_per_second: Some(0.0), max_in_flight: Some(0) }, start);
+        assert_eq!(limiter.throttle(start, 0), Throttle::Open);
+        limiter.take(10, Duration::ZERO);
+        assert!(matches!(limiter.throttle(start, 0), Throttle::Until(_)));
+    }
+
+    #[test]
+    fn reconnect_delay_backs_off_up_to_the_maximum() {
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            jitter: 0.0,
+            ..Default::default()
+        }.clamped();
+        assert_eq!(policy.delay(0), Duration::from_millis(100));
+        assert_eq!(policy.delay(3), Duration::from_millis(800));
+        assert_eq!(policy.delay(4), Duration::from_secs(1));
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(1));
+
+        let policy = ReconnectPolicy { jitter: 0.5, ..policy };
+        for attempt in 0..100 {
+            let delay = policy.delay(attempt);
+            assert!(delay <= Duration::from_secs(1) && delay >= Duration::from_millis(50), "{delay:?}");
+        }
+    }
+
+    #[test]
+    fn reconnect_policy_is_clamped() {
+        let policy = ReconnectPolicy { multiplier: -2.0, jitter: f64::NAN, ..Default::default() }.clamped();
+        assert_eq!(policy.multiplier, 1.0);
+        assert_eq!(policy.jitter, 0.0);
+        assert_eq!(policy.delay(1), policy.initial_delay);
+        assert_eq!(ReconnectPolicy { multiplier: f64::NAN, jitter: 7.0, ..Default::default() }.clamped().jitter, 1.0);
+
+        let policy = ReconnectPolicy { max_delay: Duration::MAX, jitter: 0.0, ..Default::default() }.clamped();
+        assert_eq!(policy.delay(100), Duration::MAX);
+        let policy = ReconnectPolicy { initial_delay: Duration::ZERO, multiplier: f64::INFINITY, ..Default::default() }.clamped();
+        assert_eq!(policy.delay(1), Duration::ZERO);
+    }
+
+    type Reply = tokio::sync::oneshot::Receiver<Result<(Response, Option<Bytes>)>>;
+
+    fn pending(deadline: Option<tokio::time::Instant>, now: tokio::time::Instant) -> (Pending, Reply) {
+        let (resp, rx) = tokio::sync::oneshot::channel();
+        let pending = Pending {
+            resp,
+            resend: None,
+            deadline,
+            expects_binary: false,
+            sent: now,
+            #[cfg(feature = "tracing")]
+            span: tracing::Span::none(),
+        };
+        (pending, rx)
+    }
+
+    #[test]
+    fn responders_time_out_at_the_deadline() {
+        let start = tokio::time::Instant::now();
+        let mut responders = Responders::new();
+        let (expiring, mut timed_out) = pending(Some(start + Duration::from_millis(100)), start);
+        responders.insert("1".to_string(), expiring);
+        let (waiting, mut answered) = pending(None, start);
+        responders.insert("2".to_string(), waiting);
+
+        responders.expire(start + Duration::from_millis(99));
+        assert_eq!(responders.len(), 2);
+        responders.expire(start + Duration::from_millis(100));
+        assert!(!responders.contains("1"));
+        assert!(matches!(timed_out.try_recv(), Ok(Err(Error::Timeout))));
+        assert!(responders.contains("2"));
+        assert!(answered.try_recv().is_err());
+    }
+
+    #[test]
+    fn responders_sweep_abandoned_calls() {
+        let mut responders = Responders::new();
+        let start = tokio::time::Instant::now();
+        let (abandoned, rx) = pending(None, start);
+        responders.insert("1".to_string(), abandoned);
+        drop(rx);
+        let (waiting, _rx) = pending(None, start);
+        responders.insert("2".to_string(), waiting);
+
+        responders.expire(start);
+        assert_eq!(responders.len(), 2);
+        responders.expire(start + Responders::SWEEP_INTERVAL);
+        assert!(!responders.contains("1"));
+        assert!(responders.contains("2"));
+    }
+
+    fn keepalive(start: tokio::time::Instant) -> Keepalive {
+        Keepalive::new(Some(KeepalivePolicy { interval: Duration::from_secs(15), timeout: Duration::from_secs(10) }), start)
+    }
+
+    #[test]
+    fn keepalive_pings_after_the_interval() {
+        let start = tokio::time::Instant::now();
+        let mut keepalive = keepalive(start);
+        assert!(keepalive.poll(start + Duration::from_secs(14)).unwrap().is_none());
+        let payload = keepalive.poll(start + Duration::from_secs(15)).unwrap().expect("ping");
+        // Only one ping is outstanding at a time.
+        assert!(keepalive.poll(start + Duration::from_secs(16)).unwrap().is_none());
+        assert_eq!(keepalive.pong(&payload, start + Duration::from_secs(17)), Some(Duration::from_secs(2)));
+        assert!(keepalive.poll(start + Duration::from_secs(31)).unwrap().is_none());
+        assert!(keepalive.poll(start + Duration::from_secs(32)).unwrap().is_some());
+    }
+
+    #[test]
+    fn keepalive_ignores_stale_pongs() {
+        let start = tokio::time::Instant::now();
+        let mut keepalive = keepalive(start);
+        let first = keepalive.poll(start + Duration::from_secs(15)).unwrap().expect("ping");
+        assert!(keepalive.pong(&first, start + Duration::from_secs(16)).is_some());
+        let second = keepalive.poll(start + Duration::from_secs(31)).unwrap().expect("ping");
+        assert_ne!(first, second);
+        assert_eq!(keepalive.pong(&first, start + Duration::from_secs(32)), None);
+        assert_eq!(keepalive.pong(b"unrelated", start + Duration::from_secs(32)), None);
+        assert_eq!(keepalive.pong(&second, start + Duration::from_secs(33)), Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn keepalive_times_out_without_a_pong() {
+        let start = tokio::time::Instant::now();
+        let mut keepalive = keepalive(start);
+        keepalive.poll(start + Duration::from_secs(15)).unwrap().expect("ping");
+        assert!(keepalive.poll(start + Duration::from_secs(24)).unwrap().is_none());
+        assert!(matches!(keepalive.poll(start + Duration::from_secs(25)), Err(Error::KeepaliveTimeout)));
+    }
+
+    #[test]
+    fn keepalive_rejects_late_pongs() {
+        let start = tokio::time::Instant::now();
+        let mut keepalive = keepalive(start);
+        let payload = keepalive.poll(start + Duration::from_secs(15)).unwrap().expect("ping");
+        assert_eq!(keepalive.pong(&payload, start + Duration::from_secs(26)), None);
+        assert!(matches!(keepalive.poll(start + Duration::from_secs(26)), Err(Error::KeepaliveTimeout)));
+    }
+
+    #[test]
+    fn keepalive_policy_is_clamped() {
+        let start = tokio::time::Instant::now();
+        let policy = KeepalivePolicy { interval: Duration::MAX, timeout: Duration::MAX }.clamped();
+        assert_eq!(policy.interval, KeepalivePolicy::MAX_DURATION);
+        assert_eq!(policy.timeout, KeepalivePolicy::MAX_DURATION);
+        let mut keepalive = Keepalive::new(Some(policy), start);
+        // The wakeup time is computed when the future is created.
+        drop(keepalive.wakeup());
+        let later = start + KeepalivePolicy::MAX_DURATION;
+        let payload = keepalive.poll(later).unwrap().expect("ping");
+        drop(keepalive.wakeup());
+        assert!(keepalive.poll(later + Duration::from_secs(1)).unwrap().is_none());
+        assert_eq!(keepalive.pong(&payload, later + Duration::from_secs(1)), Some(Duration::from_secs(1)));
+        drop(keepalive.wakeup());
+    }
+
+    #[test]
+    fn keepalive_without_policy_never_pings() {
+        let start = tokio::time::Instant::now();
+        let mut keepalive = Keepalive::new(None, start);
+        assert!(keepalive.poll(start + Duration::from_secs(3600)).unwrap().is_none());
+        assert_eq!(keepalive.pong(b"", start), None);
+    }
+}
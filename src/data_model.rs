@@ -174,6 +174,76 @@ impl Component {
     }
 }
 
+/// A member of a component type, as described by the engine.
+#[derive(Default,Debug,Json)]
+pub struct MemberDefinition {
+    /// Discriminator of the `Member` variant holding the value, see `Member::type_name`.
+    pub member_type: String,
+    /// Full name of the engine type, e.g. the enum type of an `enum` member.
+    pub value_type: Option<String>,
+}
+
+#[derive(Default,Debug,Json)]
+pub struct ComponentDefinition {
+    pub component_type: String,
+    pub generic_arguments: Vec<String>,
+    pub members: HashMap<String, MemberDefinition>,
+}
+
+/// Why a member map doesn't match a `ComponentDefinition`.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum MemberError {
+    Unknown { component_type: String, member: String },
+    WrongType { component_type: String, member: String, expected: String, actual: &'static str },
+}
+
+impl std::fmt::Display for MemberError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MemberError::Unknown { component_type, member } => write!(f, "{component_type} has no member {member}"),
+            MemberError::WrongType { component_type, member, expected, actual } => {
+                write!(f, "{component_type}.{member} is {expected}, not {actual}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MemberError {}
+
+impl ComponentDefinition {
+    /// Checks that every member exists and has the type the engine expects.
+    pub fn validate(&self, members: &HashMap<String, Member>) -> Result<(), MemberError> {
+        for (name, member) in members {
+            let Some(definition) = self.members.get(name) else {
+                return Err(MemberError::Unknown { component_type: self.component_type.clone(), member: name.clone() });
+            };
+            if definition.member_type != member.type_name() {
+                return Err(MemberError::WrongType {
+                    component_type: self.component_type.clone(),
+                    member: name.clone(),
+                    expected: definition.member_type.clone(),
+                    actual: member.type_name(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default,Debug,Json)]
+pub struct EnumValue {
+    pub name: String,
+    pub value: i64,
+}
+
+#[derive(Default,Debug,Json)]
+pub struct EnumDefinition {
+    pub enum_type: String,
+    pub underlying_type: String,
+    pub is_flags: bool,
+    pub values: Vec<EnumValue>,
+}
+
 #[derive(Json,Debug)]
 pub struct Slot {
     #[json(skip = "Option::is_none")]
@@ -298,3 +368,68 @@ impl Default for Slot {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definition() -> ComponentDefinition {
+        ComponentDefinition {
+            component_type: "[FrooxEngine]FrooxEngine.TextRenderer".to_string(),
+            generic_arguments: vec![],
+            members: HashMap::from([
+                ("Text".to_string(), MemberDefinition { member_type: "string".to_string(), value_type: None }),
+                ("Size".to_string(), MemberDefinition { member_type: "float".to_string(), value_type: None }),
+            ]),
+        }
+    }
+
+    #[test]
+    fn validate_accepts_matching_members() {
+        let members = HashMap::from([("Text".to_string(), Member::from("Hello")), ("Size".to_string(), Member::from(1.5f32))]);
+        assert_eq!(definition().validate(&members), Ok(()));
+        assert_eq!(definition().validate(&HashMap::new()), Ok(()));
+    }
+
+    #[test]
+    fn validate_rejects_unknown_members() {
+        let members = HashMap::from([("Color".to_string(), Member::from("red"))]);
+        assert_eq!(definition().validate(&members), Err(MemberError::Unknown {
+            component_type: "[FrooxEngine]FrooxEngine.TextRenderer".to_string(),
+            member: "Color".to_string(),
+        }));
+    }
+
+    #[test]
+    fn validate_rejects_wrong_types() {
+        let members = HashMap::from([("Size".to_string(), Member::from(Some(1.5f32)))]);
+        let error = definition().validate(&members).unwrap_err();
+        assert_eq!(error, MemberError::WrongType {
+            component_type: "[FrooxEngine]FrooxEngine.TextRenderer".to_string(),
+            member: "Size".to_string(),
+            expected: "float".to_string(),
+            actual: "float?",
+        });
+        assert_eq!(error.to_string(), "[FrooxEngine]FrooxEngine.TextRenderer.Size is float, not float?");
+    }
+
+    #[test]
+    fn type_name_matches_the_serialized_type() {
+        for json in [
+            r#"{"$type":"float3","value":{"x":1,"y":2,"z":3}}"#,
+            r#"{"$type":"float3?","value":null}"#,
+            r#"{"$type":"float3[]","values":[]}"#,
+            r#"{"$type":"string","value":"Hello"}"#,
+            r#"{"$type":"string[]","values":["Hello"]}"#,
+            r#"{"$type":"Uri[]","values":[]}"#,
+            r#"{"$type":"int?","value":3}"#,
+            r#"{"$type":"reference","targetId":null,"targetType":null}"#,
+        ] {
+            let member = Member::from_token(&Token::deserialize_str(json).unwrap()).unwrap();
+            let type_name = member.type_name();
+            assert!(json.starts_with(&format!(r#"{{"$type":"{type_name}""#)), "{type_name} from {json}");
+            let round_trip = Member::from_token(&member.to_token().unwrap()).unwrap();
+            assert_eq!(round_trip.type_name(), type_name);
+        }
+    }
+}
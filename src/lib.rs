@@ -1,5 +1,9 @@
+pub mod audio;
 pub mod data_model;
 pub mod messages;
+pub mod mesh;
 pub mod responses;
 #[cfg(feature = "client")]
-pub mod client;
\ No newline at end of file
+pub mod client;
+#[cfg(feature = "blocking")]
+pub mod blocking;
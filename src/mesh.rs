@@ -0,0 +1,235 @@
+use std::fmt::Display;
+use crate::data_model::{Color, Float2, Float3, Float4};
+use crate::messages::{ImportMeshRawData, SubmeshLayout};
+
+#[derive(Debug,Clone,Copy,Default,PartialEq,Eq)]
+pub enum Topology {
+    #[default]
+    Triangles,
+    Points,
+}
+
+impl Topology {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Topology::Triangles => "Triangles",
+            Topology::Points => "Points",
+        }
+    }
+
+    /// Number of indices that make up one primitive.
+    pub fn indices_per_primitive(&self) -> usize {
+        match self {
+            Topology::Triangles => 3,
+            Topology::Points => 1,
+        }
+    }
+}
+
+#[derive(Debug,Clone,Default,PartialEq)]
+pub struct Submesh {
+    pub topology: Topology,
+    pub indices: Vec<u32>,
+}
+
+#[derive(Debug,Clone,Copy,Default,PartialEq)]
+pub struct BoneWeight {
+    pub bone_index: i32,
+    pub weight: f32,
+}
+
+/// Geometry for `ImportMeshRawData`.
+///
+/// Every per-vertex attribute is either empty or has exactly one entry per position.
+#[derive(Debug,Clone,Default,PartialEq)]
+pub struct MeshData {
+    pub positions: Vec<Float3>,
+    pub normals: Vec<Float3>,
+    pub tangents: Vec<Float4>,
+    pub colors: Vec<Color>,
+    pub uv_channels: Vec<Vec<Float2>>,
+    /// Bone weights of each vertex. All vertices must have the same number of weights.
+    pub bone_weights: Vec<Vec<BoneWeight>>,
+    pub submeshes: Vec<Submesh>,
+}
+
+/// Why a `MeshData` can't be encoded.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum MeshError {
+    /// A vertex attribute doesn't have one entry per position.
+    AttributeLength { attribute: &'static str, expected: usize, actual: usize },
+    /// A vertex has a different number of bone weights than the first one.
+    BoneWeightCount { vertex: usize, expected: usize, actual: usize },
+    /// A submesh refers to a vertex that doesn't exist.
+    IndexOutOfRange { submesh: usize, index: u32 },
+    /// The number of indices of a submesh isn't a multiple of its primitive size.
+    IncompletePrimitive { submesh: usize, topology: Topology, index_count: usize },
+    /// The mesh has more vertices or indices than the protocol can describe.
+    TooLarge,
+}
+
+impl Display for MeshError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MeshError::AttributeLength { attribute, expected, actual } => {
+                write!(f, "{attribute} has {actual} entries, expected {expected}")
+            }
+            MeshError::BoneWeightCount { vertex, expected, actual } => {
+                write!(f, "vertex {vertex} has {actual} bone weights, expected {expected}")
+            }
+            MeshError::IndexOutOfRange { submesh, index } => write!(f, "submesh {submesh} refers to missing vertex {index}"),
+            MeshError::IncompletePrimitive { submesh, topology, index_count } => {
+                write!(f, "submesh {submesh} has {index_count} indices, which isn't a multiple of {}", topology.indices_per_primitive())
+            }
+            MeshError::TooLarge => write!(f, "mesh is too large"),
+        }
+    }
+}
+
+impl std::error::Error for MeshError {}
+
+impl MeshData {
+    pub fn new(positions: Vec<Float3>) -> Self {
+        Self {
+            positions,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_normals(mut self, normals: Vec<Float3>) -> Self {
+        self.normals = normals;
+        self
+    }
+
+    pub fn with_tangents(mut self, tangents: Vec<Float4>) -> Self {
+        self.tangents = tangents;
+        self
+    }
+
+    pub fn with_colors(mut self, colors: Vec<Color>) -> Self {
+        self.colors = colors;
+        self
+    }
+
+    /// Adds the next UV channel.
+    pub fn with_uv_channel(mut self, uvs: Vec<Float2>) -> Self {
+        self.uv_channels.push(uvs);
+        self
+    }
+
+    pub fn with_bone_weights(mut self, bone_weights: Vec<Vec<BoneWeight>>) -> Self {
+        self.bone_weights = bone_weights;
+        self
+    }
+
+    pub fn with_submesh(mut self, topology: Topology, indices: Vec<u32>) -> Self {
+        self.submeshes.push(Submesh { topology, indices });
+        self
+    }
+
+    pub fn with_triangles(self, indices: Vec<u32>) -> Self {
+        self.with_submesh(Topology::Triangles, indices)
+    }
+
+    pub fn with_points(self, indices: Vec<u32>) -> Self {
+        self.with_submesh(Topology::Points, indices)
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn validate(&self) -> Result<(), MeshError> {
+        let vertex_count = self.vertex_count();
+        let check = |attribute: &'static str, actual: usize| {
+            if actual != 0 && actual != vertex_count {
+                return Err(MeshError::AttributeLength { attribute, expected: vertex_count, actual });
+            }
+            Ok(())
+        };
+        check("normals", self.normals.len())?;
+        check("tangents", self.tangents.len())?;
+        check("colors", self.colors.len())?;
+        for uvs in &self.uv_channels {
+            if uvs.len() != vertex_count {
+                return Err(MeshError::AttributeLength { attribute: "uv_channels", expected: vertex_count, actual: uvs.len() });
+            }
+        }
+        check("bone_weights", self.bone_weights.len())?;
+        let bone_weight_count = self.bone_weight_count();
+        for (vertex, weights) in self.bone_weights.iter().enumerate() {
+            if weights.len() != bone_weight_count {
+                return Err(MeshError::BoneWeightCount { vertex, expected: bone_weight_count, actual: weights.len() });
+            }
+        }
+        for (submesh, Submesh { topology, indices }) in self.submeshes.iter().enumerate() {
+            if !indices.len().is_multiple_of(topology.indices_per_primitive()) {
+                return Err(MeshError::IncompletePrimitive { submesh, topology: *topology, index_count: indices.len() });
+            }
+            if let Some(&index) = indices.iter().find(|&&index| index as usize >= vertex_count) {
+                return Err(MeshError::IndexOutOfRange { submesh, index });
+            }
+        }
+        Ok(())
+    }
+
+    fn bone_weight_count(&self) -> usize {
+        self.bone_weights.first().map_or(0, Vec::len)
+    }
+
+    /// Validates the mesh and builds the message header together with its binary payload.
+    ///
+    /// The message id is left empty so the client can assign one.
+    pub fn encode(&self) -> Result<(ImportMeshRawData, Vec<u8>), MeshError> {
+        self.validate()?;
+        let count = |n: usize| i32::try_from(n).map_err(|_| MeshError::TooLarge);
+        let header = ImportMeshRawData {
+            message_id: String::new(),
+            vertex_count: count(self.vertex_count())?,
+            has_normals: !self.normals.is_empty(),
+            has_tangents: !self.tangents.is_empty(),
+            has_colors: !self.colors.is_empty(),
+            uv_channel_count: count(self.uv_channels.len())?,
+            bone_weight_count: count(self.bone_weight_count())?,
+            submeshes: self.submeshes.iter()
+                .map(|submesh| Ok(SubmeshLayout { topology: submesh.topology.name().to_string(), index_count: count(submesh.indices.len())? }))
+                .collect::<Result<_, MeshError>>()?,
+        };
+
+        let mut data = Vec::with_capacity(header.binary_len());
+        let mut put = |values: &[f32]| {
+            for value in values {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+        };
+        for v in &self.positions {
+            put(&[v.x, v.y, v.z]);
+        }
+        for v in &self.normals {
+            put(&[v.x, v.y, v.z]);
+        }
+        for v in &self.tangents {
+            put(&[v.x, v.y, v.z, v.w]);
+        }
+        for c in &self.colors {
+            put(&[c.r, c.g, c.b, c.a]);
+        }
+        for uvs in &self.uv_channels {
+            for v in uvs {
+                put(&[v.x, v.y]);
+            }
+        }
+        for weights in &self.bone_weights {
+            for weight in weights {
+                data.extend_from_slice(&weight.bone_index.to_le_bytes());
+                data.extend_from_slice(&weight.weight.to_le_bytes());
+            }
+        }
+        for submesh in &self.submeshes {
+            for &index in &submesh.indices {
+                data.extend_from_slice(&(index as i32).to_le_bytes());
+            }
+        }
+        Ok((header, data))
+    }
+}
@@ -1,4 +1,5 @@
 use crate::data_model::{FieldBool, FieldFloat3, FieldFloatQ, FieldString, Float3, Member, Reference, Slot};
+use crate::responses::{AssetData, ComponentData, ComponentDefinitionData, EnumDefinitionData, Response, ResponseData, SessionData, SlotData};
 use std::collections::HashMap;
 use resoxide_json::Json;
 
@@ -146,6 +147,89 @@ pub struct ImportTexture2DRawDataHDR {
     pub height: i32,
 }
 
+#[derive(Debug,Default,Json)]
+pub struct ImportMeshFile {
+    pub message_id: String,
+    pub file_path: String,
+}
+
+#[derive(Debug,Default,Json)]
+pub struct SubmeshLayout {
+    /// `Triangles` or `Points`.
+    pub topology: String,
+    pub index_count: i32,
+}
+
+/// Imports a mesh from a binary payload, usually built with `MeshData::encode`.
+///
+/// The payload holds little endian values, in this order: positions (3 f32), normals (3 f32),
+/// tangents (4 f32), colors (4 f32), each UV channel (2 f32), bone weights (i32 bone index and
+/// f32 weight) and finally the indices (i32) of each submesh.
+#[derive(Debug,Default,Json)]
+pub struct ImportMeshRawData {
+    pub message_id: String,
+    pub vertex_count: i32,
+    pub has_normals: bool,
+    pub has_tangents: bool,
+    pub has_colors: bool,
+    pub uv_channel_count: i32,
+    /// Number of bone weights per vertex.
+    pub bone_weight_count: i32,
+    pub submeshes: Vec<SubmeshLayout>,
+}
+
+impl ImportMeshRawData {
+    /// Number of bytes the binary payload must have.
+    pub fn binary_len(&self) -> usize {
+        let count = |n: i32| n.max(0) as usize;
+        let mut vertex_size = 12 + count(self.uv_channel_count) * 8 + count(self.bone_weight_count) * 8;
+        if self.has_normals {
+            vertex_size += 12;
+        }
+        if self.has_tangents {
+            vertex_size += 16;
+        }
+        if self.has_colors {
+            vertex_size += 16;
+        }
+        let index_count: usize = self.submeshes.iter().map(|submesh| count(submesh.index_count)).sum();
+        count(self.vertex_count) * vertex_size + index_count * 4
+    }
+}
+
+#[derive(Debug,Default,Json)]
+pub struct ImportAudioClipFile {
+    pub message_id: String,
+    pub file_path: String,
+}
+
+/// Imports an audio clip from interleaved little endian f32 samples, see `audio::encode_samples`.
+#[derive(Debug,Default,Json)]
+pub struct ImportAudioClipRawData {
+    pub message_id: String,
+    /// Number of samples per channel.
+    pub sample_count: i32,
+    pub sample_rate: i32,
+    pub channel_count: i32,
+}
+
+#[derive(Debug,Default,Json)]
+pub struct RequestSessionData {
+    pub message_id: String,
+}
+
+#[derive(Debug,Default,Json)]
+pub struct GetComponentDefinition {
+    pub message_id: String,
+    pub component_type: String,
+}
+
+#[derive(Debug,Default,Json)]
+pub struct GetEnumDefinition {
+    pub message_id: String,
+    pub enum_type: String,
+}
+
 #[derive(Debug,Json)]
 pub enum Message {
     GetSlot(GetSlot),
@@ -162,6 +246,13 @@ pub enum Message {
     ImportTexture2DRawData(ImportTexture2DRawData),
     #[json(rename = "importTexture2DRawDataHDR")]
     ImportTexture2DRawDataHDR(ImportTexture2DRawDataHDR),
+    ImportMeshFile(ImportMeshFile),
+    ImportMeshRawData(ImportMeshRawData),
+    ImportAudioClipFile(ImportAudioClipFile),
+    ImportAudioClipRawData(ImportAudioClipRawData),
+    RequestSessionData(RequestSessionData),
+    GetComponentDefinition(GetComponentDefinition),
+    GetEnumDefinition(GetEnumDefinition),
 }
 
 impl Message {
@@ -178,9 +269,39 @@ impl Message {
             Message::ImportTexture2DFile(msg) => Message::ImportTexture2DFile(ImportTexture2DFile { message_id, ..msg }),
             Message::ImportTexture2DRawData(msg ) => Message::ImportTexture2DRawData(ImportTexture2DRawData { message_id, ..msg }),
             Message::ImportTexture2DRawDataHDR(msg  ) => Message::ImportTexture2DRawDataHDR(ImportTexture2DRawDataHDR { message_id, ..msg }),
+            Message::ImportMeshFile(msg) => Message::ImportMeshFile(ImportMeshFile { message_id, ..msg }),
+            Message::ImportMeshRawData(msg) => Message::ImportMeshRawData(ImportMeshRawData { message_id, ..msg }),
+            Message::ImportAudioClipFile(msg) => Message::ImportAudioClipFile(ImportAudioClipFile { message_id, ..msg }),
+            Message::ImportAudioClipRawData(msg) => Message::ImportAudioClipRawData(ImportAudioClipRawData { message_id, ..msg }),
+            Message::RequestSessionData(_) => Message::RequestSessionData(RequestSessionData { message_id }),
+            Message::GetComponentDefinition(msg) => Message::GetComponentDefinition(GetComponentDefinition { message_id, ..msg }),
+            Message::GetEnumDefinition(msg) => Message::GetEnumDefinition(GetEnumDefinition { message_id, ..msg }),
         }
     }
     
+    pub fn message_id(&self) -> &str {
+        match self {
+            Message::GetSlot(msg) => &msg.message_id,
+            Message::AddSlot(msg) => &msg.message_id,
+            Message::UpdateSlot(msg) => &msg.message_id,
+            Message::RemoveSlot(msg) => &msg.message_id,
+            Message::GetComponent(msg) => &msg.message_id,
+            Message::AddComponent(msg) => &msg.message_id,
+            Message::UpdateComponent(msg) => &msg.message_id,
+            Message::RemoveComponent(msg) => &msg.message_id,
+            Message::ImportTexture2DFile(msg) => &msg.message_id,
+            Message::ImportTexture2DRawData(msg) => &msg.message_id,
+            Message::ImportTexture2DRawDataHDR(msg) => &msg.message_id,
+            Message::ImportMeshFile(msg) => &msg.message_id,
+            Message::ImportMeshRawData(msg) => &msg.message_id,
+            Message::ImportAudioClipFile(msg) => &msg.message_id,
+            Message::ImportAudioClipRawData(msg) => &msg.message_id,
+            Message::RequestSessionData(msg) => &msg.message_id,
+            Message::GetComponentDefinition(msg) => &msg.message_id,
+            Message::GetEnumDefinition(msg) => &msg.message_id,
+        }
+    }
+
     pub fn has_binary(&self) -> bool {
         match self {
             Message::GetSlot(_) => false,
@@ -194,10 +315,299 @@ impl Message {
             Message::ImportTexture2DFile(_) => false,
             Message::ImportTexture2DRawData(_) => true,
             Message::ImportTexture2DRawDataHDR(_) => true,
+            Message::ImportMeshFile(_) => false,
+            Message::ImportMeshRawData(_) => true,
+            Message::ImportAudioClipFile(_) => false,
+            Message::ImportAudioClipRawData(_) => true,
+            Message::RequestSessionData(_) => false,
+            Message::GetComponentDefinition(_) => false,
+            Message::GetEnumDefinition(_) => false,
         }
     }
     
+    /// Checks that a payload of `len` bytes, or none, is what the message requires.
+    pub fn check_payload_len(&self, len: Option<usize>) -> Result<(), PayloadError> {
+        match (self.has_binary(), len) {
+            (true, None) => Err(PayloadError::Missing(self.kind())),
+            (false, Some(_)) => Err(PayloadError::Unexpected(self.kind())),
+            (true, Some(actual)) => match self.binary_len() {
+                Some(expected) if expected != actual => Err(PayloadError::Length { kind: self.kind(), expected, actual }),
+                _ => Ok(()),
+            },
+            (false, None) => Ok(()),
+        }
+    }
+
+    /// Number of bytes the binary payload must have, if the message determines it.
+    pub fn binary_len(&self) -> Option<usize> {
+        let pixels = |width: i32, height: i32| width.max(0) as usize * height.max(0) as usize;
+        match self {
+            Message::GetSlot(_) => None,
+            Message::AddSlot(_) => None,
+            Message::UpdateSlot(_) => None,
+            Message::RemoveSlot(_) => None,
+            Message::GetComponent(_) => None,
+            Message::AddComponent(_) => None,
+            Message::UpdateComponent(_) => None,
+            Message::RemoveComponent(_) => None,
+            Message::ImportTexture2DFile(_) => None,
+            // RGBA, one byte per channel.
+            Message::ImportTexture2DRawData(msg) => Some(pixels(msg.width, msg.height) * 4),
+            // RGBA, one f32 per channel.
+            Message::ImportTexture2DRawDataHDR(msg) => Some(pixels(msg.width, msg.height) * 16),
+            Message::ImportMeshFile(_) => None,
+            Message::ImportMeshRawData(msg) => Some(msg.binary_len()),
+            Message::ImportAudioClipFile(_) => None,
+            // Interleaved, one f32 per sample.
+            Message::ImportAudioClipRawData(msg) => Some(msg.sample_count.max(0) as usize * msg.channel_count.max(0) as usize * 4),
+            Message::RequestSessionData(_) => None,
+            Message::GetComponentDefinition(_) => None,
+            Message::GetEnumDefinition(_) => None,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::GetSlot(_) => "GetSlot",
+            Message::AddSlot(_) => "AddSlot",
+            Message::UpdateSlot(_) => "UpdateSlot",
+            Message::RemoveSlot(_) => "RemoveSlot",
+            Message::GetComponent(_) => "GetComponent",
+            Message::AddComponent(_) => "AddComponent",
+            Message::UpdateComponent(_) => "UpdateComponent",
+            Message::RemoveComponent(_) => "RemoveComponent",
+            Message::ImportTexture2DFile(_) => "ImportTexture2DFile",
+            Message::ImportTexture2DRawData(_) => "ImportTexture2DRawData",
+            Message::ImportTexture2DRawDataHDR(_) => "ImportTexture2DRawDataHDR",
+            Message::ImportMeshFile(_) => "ImportMeshFile",
+            Message::ImportMeshRawData(_) => "ImportMeshRawData",
+            Message::ImportAudioClipFile(_) => "ImportAudioClipFile",
+            Message::ImportAudioClipRawData(_) => "ImportAudioClipRawData",
+            Message::RequestSessionData(_) => "RequestSessionData",
+            Message::GetComponentDefinition(_) => "GetComponentDefinition",
+            Message::GetEnumDefinition(_) => "GetEnumDefinition",
+        }
+    }
+
+    /// Whether sending the message again has no additional effect.
+    pub fn is_idempotent(&self) -> bool {
+        match self {
+            Message::GetSlot(_) => true,
+            Message::AddSlot(_) => false,
+            Message::UpdateSlot(_) => false,
+            Message::RemoveSlot(_) => false,
+            Message::GetComponent(_) => true,
+            Message::AddComponent(_) => false,
+            Message::UpdateComponent(_) => false,
+            Message::RemoveComponent(_) => false,
+            Message::ImportTexture2DFile(_) => false,
+            Message::ImportTexture2DRawData(_) => false,
+            Message::ImportTexture2DRawDataHDR(_) => false,
+            Message::ImportMeshFile(_) => false,
+            Message::ImportMeshRawData(_) => false,
+            Message::ImportAudioClipFile(_) => false,
+            Message::ImportAudioClipRawData(_) => false,
+            Message::RequestSessionData(_) => true,
+            Message::GetComponentDefinition(_) => true,
+            Message::GetEnumDefinition(_) => true,
+        }
+    }
+
     pub fn serialize(&self) -> resoxide_json::Result<String> {
         self.to_token()?.serialize()
     }
-}
\ No newline at end of file
+
+    pub fn deserialize(s: &str) -> resoxide_json::Result<Message> {
+        let token = resoxide_json::Token::deserialize_str(s)?;
+        Self::from_token(&token)
+    }
+}
+
+/// Why a binary payload can't be sent with a message, see `Message::check_payload_len`.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum PayloadError {
+    /// The message requires a binary payload but none was given.
+    Missing(&'static str),
+    /// A binary payload was given for a message that doesn't take one.
+    Unexpected(&'static str),
+    /// The binary payload doesn't have the size the message describes.
+    Length { kind: &'static str, expected: usize, actual: usize },
+}
+
+impl std::fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PayloadError::Missing(kind) => write!(f, "{kind} requires a binary payload"),
+            PayloadError::Unexpected(kind) => write!(f, "{kind} doesn't take a binary payload"),
+            PayloadError::Length { kind, expected, actual } => {
+                write!(f, "{kind} payload has {actual} bytes, expected {expected}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PayloadError {}
+
+/// Why incoming frames don't form a message, see `MessageFrames`.
+#[derive(Debug)]
+pub enum FrameError {
+    Json(resoxide_json::Error),
+    /// The `orphan` should have been followed by a binary frame, but a text frame came first. The
+    /// text frame is decoded anyway: `next` is its message, or `None` if it waits for its own binary frame.
+    MissingBinary { orphan: Box<Message>, next: Option<Box<Message>> },
+    /// A binary frame arrived without a message waiting for it.
+    UnexpectedBinary,
+    /// The binary frame doesn't have the size the message describes.
+    PayloadLength { message: Box<Message>, expected: usize, actual: usize },
+}
+
+impl std::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        <Self as std::fmt::Debug>::fmt(self, f)
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<resoxide_json::Error> for FrameError {
+    fn from(e: resoxide_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+/// Pairs the frames received by a server with the messages they belong to.
+///
+/// A message with `has_binary` is held back until its binary frame arrives.
+#[derive(Debug,Default)]
+pub struct MessageFrames {
+    awaiting_binary: Option<Message>,
+}
+
+impl MessageFrames {
+    /// Decodes a text frame. Returns `None` if the message still waits for its binary frame.
+    ///
+    /// If the previous message never got its binary frame, it is returned as `MissingBinary` along
+    /// with the message decoded from `text`. A frame that can't be decoded leaves the previous
+    /// message waiting.
+    pub fn text(&mut self, text: &str) -> Result<Option<Message>, FrameError> {
+        let msg = Message::deserialize(text)?;
+        let orphan = self.awaiting_binary.take();
+        let next = if msg.has_binary() {
+            self.awaiting_binary = Some(msg);
+            None
+        } else {
+            Some(msg)
+        };
+        match orphan {
+            Some(orphan) => Err(FrameError::MissingBinary { orphan: Box::new(orphan), next: next.map(Box::new) }),
+            None => Ok(next),
+        }
+    }
+
+    /// Completes the message waiting for a binary frame.
+    pub fn binary<B: AsRef<[u8]>>(&mut self, data: B) -> Result<(Message, B), FrameError> {
+        let Some(msg) = self.awaiting_binary.take() else {
+            return Err(FrameError::UnexpectedBinary);
+        };
+        let actual = data.as_ref().len();
+        match msg.binary_len() {
+            Some(expected) if expected != actual => Err(FrameError::PayloadLength { message: Box::new(msg), expected, actual }),
+            _ => Ok((msg, data)),
+        }
+    }
+
+    /// Whether a message is waiting for its binary frame.
+    pub fn is_awaiting_binary(&self) -> bool {
+        self.awaiting_binary.is_some()
+    }
+}
+
+/// A message together with the type of response it is answered with.
+pub trait Request: Into<Message> {
+    type Response: TryFrom<Response, Error = Response>;
+}
+
+macro_rules! request {
+    ($($message:ident => $response:ty),* $(,)?) => {
+        $(
+            impl From<$message> for Message {
+                fn from(value: $message) -> Self {
+                    Message::$message(value)
+                }
+            }
+
+            impl Request for $message {
+                type Response = $response;
+            }
+        )*
+    };
+}
+
+request! {
+    GetSlot => SlotData,
+    AddSlot => ResponseData,
+    UpdateSlot => ResponseData,
+    RemoveSlot => ResponseData,
+    GetComponent => ComponentData,
+    AddComponent => ResponseData,
+    UpdateComponent => ResponseData,
+    RemoveComponent => ResponseData,
+    ImportTexture2DFile => ResponseData,
+    ImportTexture2DRawData => ResponseData,
+    ImportTexture2DRawDataHDR => ResponseData,
+    ImportMeshFile => AssetData,
+    ImportMeshRawData => AssetData,
+    ImportAudioClipFile => AssetData,
+    ImportAudioClipRawData => AssetData,
+    RequestSessionData => SessionData,
+    GetComponentDefinition => ComponentDefinitionData,
+    GetEnumDefinition => EnumDefinitionData,
+}
+
+pub trait MessageIdGenerator: Send {
+    fn next_id(&mut self) -> String;
+}
+
+impl<F: FnMut() -> String + Send> MessageIdGenerator for F {
+    fn next_id(&mut self) -> String {
+        self()
+    }
+}
+
+/// Generates `{prefix}1`, `{prefix}2`, ...
+#[derive(Debug,Clone)]
+pub struct SequentialIds {
+    prefix: String,
+    counter: u64,
+}
+
+impl SequentialIds {
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self { prefix: prefix.into(), counter: 0 }
+    }
+}
+
+impl Default for SequentialIds {
+    fn default() -> Self {
+        Self::new("Msg")
+    }
+}
+
+impl MessageIdGenerator for SequentialIds {
+    fn next_id(&mut self) -> String {
+        self.counter += 1;
+        format!("{}{}", self.prefix, self.counter)
+    }
+}
+
+/// Generates random (version 4) UUIDs.
+#[cfg(feature = "uuid")]
+#[derive(Debug,Clone,Copy,Default)]
+pub struct UuidIds;
+
+#[cfg(feature = "uuid")]
+impl MessageIdGenerator for UuidIds {
+    fn next_id(&mut self) -> String {
+        uuid::Uuid::new_v4().to_string()
+    }
+}
@@ -1,4 +1,5 @@
-use crate::data_model::{Component, Slot};
+use std::fmt::Display;
+use crate::data_model::{Component, ComponentDefinition, EnumDefinition, Slot};
 use resoxide_json::Json;
 
 #[derive(Debug,Default,Json)]
@@ -20,9 +21,84 @@ pub struct ComponentData {
 
 #[derive(Debug,Default,Json)]
 pub struct ResponseData {
+    pub source_message_id: String,
+    pub success: bool,
+    pub error_info: Option<String>,
+}
+
+/// The world and versions the connection is talking to.
+#[derive(Debug,Default,Json)]
+pub struct SessionData {
+    pub source_message_id: String,
+    pub success: bool,
+    pub error_info: Option<String>,
+    pub world_name: Option<String>,
+    pub session_id: Option<String>,
+    pub resonite_version: Option<String>,
+    pub resonite_link_version: Option<String>,
+    /// Unique id of the user running ResoniteLink.
+    pub local_user_id: Option<String>,
+}
+
+#[derive(Debug,Default,Json)]
+pub struct ComponentDefinitionData {
+    pub source_message_id: String,
+    pub success: bool,
+    pub error_info: Option<String>,
+    pub definition: ComponentDefinition,
+}
+
+#[derive(Debug,Default,Json)]
+pub struct EnumDefinitionData {
+    pub source_message_id: String,
+    pub success: bool,
+    pub error_info: Option<String>,
+    pub definition: EnumDefinition,
+}
+
+/// Answer to an import, referring to the asset that was created.
+#[derive(Debug,Default,Json)]
+pub struct AssetData {
+    pub source_message_id: String,
+    pub success: bool,
+    pub error_info: Option<String>,
+    pub asset_url: Option<String>,
+}
+
+/// A request that Resonite answered with `success: false`.
+#[derive(Debug,Clone,Default,PartialEq,Eq)]
+pub struct ServerError {
+    pub source_message_id: String,
+    pub error_info: Option<String>,
+    /// The kind of message that was rejected (see `Message::kind`), if known.
+    pub message_kind: Option<&'static str>,
+}
+
+impl ServerError {
+    pub fn with_message_kind(self, message_kind: &'static str) -> Self {
+        Self { message_kind: Some(message_kind), ..self }
+    }
+}
+
+impl Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.message_kind {
+            Some(kind) => write!(f, "{} {} failed", kind, self.source_message_id)?,
+            None => write!(f, "{} failed", self.source_message_id)?,
+        }
+        match &self.error_info {
+            Some(error_info) => write!(f, ": {}", error_info),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for ServerError {}
+
+/// The part every response has in common, used when the full response can't be decoded.
+#[derive(Debug,Default,Json)]
+struct ResponseHeader {
     source_message_id: String,
-    success: bool,
-    error_info: Option<String>,
 }
 
 #[derive(Debug,Json)]
@@ -30,6 +106,10 @@ pub enum Response {
     Response(ResponseData),
     SlotData(SlotData),
     ComponentData(ComponentData),
+    SessionData(SessionData),
+    ComponentDefinitionData(ComponentDefinitionData),
+    EnumDefinitionData(EnumDefinitionData),
+    AssetData(AssetData),
 }
 
 impl Response {
@@ -38,11 +118,104 @@ impl Response {
             Response::Response(ResponseData { source_message_id, .. }) => source_message_id,
             Response::SlotData(SlotData { source_message_id, .. }) => source_message_id,
             Response::ComponentData(ComponentData { source_message_id, .. }) => source_message_id,
+            Response::SessionData(SessionData { source_message_id, .. }) => source_message_id,
+            Response::ComponentDefinitionData(ComponentDefinitionData { source_message_id, .. }) => source_message_id,
+            Response::EnumDefinitionData(EnumDefinitionData { source_message_id, .. }) => source_message_id,
+            Response::AssetData(AssetData { source_message_id, .. }) => source_message_id,
         }
     }
     
+    pub fn success(&self) -> bool {
+        match self {
+            Response::Response(data) => data.success(),
+            Response::SlotData(data) => data.success(),
+            Response::ComponentData(data) => data.success(),
+            Response::SessionData(data) => data.success(),
+            Response::ComponentDefinitionData(data) => data.success(),
+            Response::EnumDefinitionData(data) => data.success(),
+            Response::AssetData(data) => data.success(),
+        }
+    }
+
+    pub fn error_info(&self) -> Option<&str> {
+        match self {
+            Response::Response(data) => data.error_info(),
+            Response::SlotData(data) => data.error_info(),
+            Response::ComponentData(data) => data.error_info(),
+            Response::SessionData(data) => data.error_info(),
+            Response::ComponentDefinitionData(data) => data.error_info(),
+            Response::EnumDefinitionData(data) => data.error_info(),
+            Response::AssetData(data) => data.error_info(),
+        }
+    }
+
+    /// Turns a response with `success: false` into a `ServerError`.
+    pub fn into_result(self) -> Result<Response, ServerError> {
+        if self.success() {
+            return Ok(self);
+        }
+        Err(ServerError {
+            source_message_id: self.message_id().to_string(),
+            error_info: self.error_info().map(str::to_string),
+            message_kind: None,
+        })
+    }
+
+    pub fn serialize(&self) -> resoxide_json::Result<String> {
+        self.to_token()?.serialize()
+    }
+
     pub fn deserialize(s: &str) -> resoxide_json::Result<Response> {
         let token = resoxide_json::Token::deserialize_str(s)?;
         Self::from_token(&token)
     }
+
+    /// Extracts the `sourceMessageId` of a response without decoding the rest of it.
+    pub fn peek_message_id(s: &str) -> Option<String> {
+        let token = resoxide_json::Token::deserialize_str(s).ok()?;
+        let header = ResponseHeader::from_token(&token).ok()?;
+        Some(header.source_message_id).filter(|msg_id| !msg_id.is_empty())
+    }
+}
+
+/// Accessors shared by every response type, and the conversion out of its `Response` variant.
+macro_rules! response {
+    ($($variant:ident => $data:ident),* $(,)?) => {
+        $(
+            impl $data {
+                pub fn source_message_id(&self) -> &str {
+                    &self.source_message_id
+                }
+
+                pub fn success(&self) -> bool {
+                    self.success
+                }
+
+                pub fn error_info(&self) -> Option<&str> {
+                    self.error_info.as_deref()
+                }
+            }
+
+            impl TryFrom<Response> for $data {
+                type Error = Response;
+
+                fn try_from(value: Response) -> Result<Self, Self::Error> {
+                    match value {
+                        Response::$variant(data) => Ok(data),
+                        other => Err(other),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+response! {
+    Response => ResponseData,
+    SlotData => SlotData,
+    ComponentData => ComponentData,
+    SessionData => SessionData,
+    ComponentDefinitionData => ComponentDefinitionData,
+    EnumDefinitionData => EnumDefinitionData,
+    AssetData => AssetData,
 }
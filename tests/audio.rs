@@ -0,0 +1,31 @@
+use resoxide_link::audio::{encode_samples, AudioError};
+use resoxide_link::messages::Message;
+
+#[test]
+fn interleaved_layout() {
+    let samples = [0.25, -0.25, 0.5, -0.5, 1.0, -1.0];
+    let (msg, data) = encode_samples(&samples, 44100, 2).expect("encode samples");
+    assert!(msg.message_id.is_empty());
+    assert_eq!(msg.sample_count, 3);
+    assert_eq!(msg.sample_rate, 44100);
+    assert_eq!(msg.channel_count, 2);
+
+    let expected: Vec<u8> = samples.iter().flat_map(|sample: &f32| sample.to_le_bytes()).collect();
+    assert_eq!(data, expected);
+    assert_eq!(Message::from(msg).binary_len(), Some(data.len()));
+}
+
+#[test]
+fn empty_clip() {
+    let (msg, data) = encode_samples(&[], 48000, 1).expect("encode samples");
+    assert_eq!(msg.sample_count, 0);
+    assert!(data.is_empty());
+}
+
+#[test]
+fn rejects_invalid_clips() {
+    assert_eq!(encode_samples(&[0.0], 0, 1).unwrap_err(), AudioError::InvalidFormat { sample_rate: 0, channel_count: 1 });
+    assert_eq!(encode_samples(&[0.0], 48000, 0).unwrap_err(), AudioError::InvalidFormat { sample_rate: 48000, channel_count: 0 });
+    assert_eq!(encode_samples(&[0.0; 5], 48000, 2).unwrap_err(), AudioError::IncompleteFrame { sample_count: 5, channel_count: 2 });
+    assert_eq!(encode_samples(&[0.0; 3], u32::MAX, 1).unwrap_err(), AudioError::TooLarge);
+}
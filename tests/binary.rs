@@ -0,0 +1,105 @@
+#![cfg(all(feature = "client", feature = "blocking"))]
+
+use std::net::TcpListener;
+use std::thread::JoinHandle;
+use resoxide_link::blocking::{self, BlockingClient};
+use resoxide_link::client::{self, Bytes, Client};
+use resoxide_link::messages::{GetComponent, ImportTexture2DRawData, Message, PayloadError};
+use resoxide_link::responses::{ComponentData, Response};
+
+const PAYLOAD: &[u8] = &[1, 2, 3, 4];
+
+/// Answers every message with a response followed by a binary frame.
+fn serve() -> (String, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut socket = tungstenite::accept(stream).unwrap();
+        loop {
+            let text = match socket.read() {
+                Ok(tungstenite::Message::Text(text)) => text,
+                Ok(_) => continue,
+                Err(_) => return,
+            };
+            let msg = Message::deserialize(text.as_str()).unwrap();
+            let response = Response::ComponentData(ComponentData {
+                source_message_id: msg.message_id().to_string(),
+                success: true,
+                ..Default::default()
+            });
+            socket.send(tungstenite::Message::text(response.serialize().unwrap())).unwrap();
+            socket.send(tungstenite::Message::binary(PAYLOAD)).unwrap();
+        }
+    });
+    (url, server)
+}
+
+fn get_component() -> Message {
+    GetComponent { message_id: Default::default(), component_id: "Comp1".to_string() }.into()
+}
+
+#[test]
+fn client_pairs_binary_with_response() {
+    let (url, server) = serve();
+    let client = Client::builder(url).unwrap().session_info(None).blocking_connect().unwrap();
+    let (response, data) = client.blocking_call_with_binary(get_component(), None).unwrap();
+    assert!(matches!(response, Response::ComponentData(_)));
+    assert_eq!(data, Some(Bytes::from_static(PAYLOAD)));
+    client.blocking_close().unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn blocking_client_pairs_binary_with_response() {
+    let (url, server) = serve();
+    let mut client = BlockingClient::connect(url).unwrap();
+    let (response, data) = client.call_with_binary(get_component(), None).unwrap();
+    assert!(matches!(response, Response::ComponentData(_)));
+    assert_eq!(data, Some(Bytes::from_static(PAYLOAD)));
+    client.close().unwrap();
+    server.join().unwrap();
+}
+
+fn texture() -> Message {
+    ImportTexture2DRawData { message_id: Default::default(), width: 1, height: 1, color_profile: "sRGB".to_string() }.into()
+}
+
+/// Messages with a payload that doesn't fit them, and the error they fail with.
+fn mismatched_payloads() -> Vec<(Message, Option<Bytes>, PayloadError)> {
+    vec![
+        (get_component(), Some(Bytes::from_static(PAYLOAD)), PayloadError::Unexpected("GetComponent")),
+        (texture(), None, PayloadError::Missing("ImportTexture2DRawData")),
+        (
+            texture(),
+            Some(Bytes::from_static(&[0; 3])),
+            PayloadError::Length { kind: "ImportTexture2DRawData", expected: 4, actual: 3 },
+        ),
+    ]
+}
+
+#[test]
+fn clients_reject_mismatched_payloads() {
+    let (url, server) = serve();
+    let client = Client::builder(url).unwrap().session_info(None).blocking_connect().unwrap();
+    for (msg, data, expected) in mismatched_payloads() {
+        match client.blocking_call(msg, data.map(Into::into)) {
+            Err(client::Error::InvalidPayload(e)) => assert_eq!(e, expected),
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+    client.blocking_close().unwrap();
+    server.join().unwrap();
+
+    let (url, server) = serve();
+    let mut client = BlockingClient::connect(url).unwrap();
+    for (msg, data, expected) in mismatched_payloads() {
+        match client.send(msg, data) {
+            Err(blocking::Error::InvalidPayload(e)) => assert_eq!(e, expected),
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+    assert_eq!(client.pending(), 0);
+    client.close().unwrap();
+    server.join().unwrap();
+}
@@ -0,0 +1,52 @@
+#![cfg(all(feature = "client", feature = "blocking"))]
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use resoxide_link::blocking::{BlockingClient, Bytes, Error, Interceptor, Result};
+use resoxide_link::messages::{Message, RemoveSlot};
+use resoxide_link::responses::Response;
+
+/// Logs the hooks it runs, and refuses to remove `Protected`.
+struct Log {
+    name: &'static str,
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+impl Interceptor for Log {
+    fn before_send(&mut self, msg: &mut Message, _: Option<&Bytes>) -> Result<()> {
+        self.log.lock().unwrap().push(format!("{} before {}", self.name, msg.message_id()));
+        match msg {
+            Message::RemoveSlot(RemoveSlot { slot_id, .. }) if slot_id == "Protected" => Err(Error::Rejected(slot_id.clone())),
+            _ => Ok(()),
+        }
+    }
+
+    fn after_receive(&mut self, response: &Response, _: Duration) {
+        self.log.lock().unwrap().push(format!("{} after {}", self.name, response.message_id()));
+    }
+}
+
+#[test]
+fn interceptors_run_around_each_request() {
+    let (url, server) = common::serve(1, |_, stream| common::answer_all(&mut common::accept(stream)));
+    let log = Arc::new(Mutex::new(vec![]));
+    let mut client = BlockingClient::connect(url).unwrap();
+    client.add_interceptor(Log { name: "first", log: log.clone() });
+    client.add_interceptor(Log { name: "second", log: log.clone() });
+
+    let msg_id = client.send(common::remove_slot("Slot1"), None).unwrap();
+    client.receive(&msg_id).unwrap();
+    assert!(matches!(client.send(common::remove_slot("Protected"), None), Err(Error::Rejected(_))));
+    assert_eq!(client.pending(), 0);
+    assert_eq!(*log.lock().unwrap(), [
+        format!("first before {msg_id}"),
+        format!("second before {msg_id}"),
+        format!("second after {msg_id}"),
+        format!("first after {msg_id}"),
+        "first before Msg2".to_string(),
+    ]);
+    client.close().unwrap();
+    server.join().unwrap();
+}
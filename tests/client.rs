@@ -0,0 +1,285 @@
+#![cfg(feature = "client")]
+
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_stream::StreamExt;
+use resoxide_link::client::{Client, ClientBuilder, ConnectionState, Diagnostic, Error, RateLimit};
+use resoxide_link::messages::{Message, RemoveSlot, SequentialIds};
+use resoxide_link::responses::{Response, ResponseData, ServerError};
+
+#[test]
+fn pending_call_gets_the_terminal_error() {
+    let (url, server) = common::serve(1, |_, stream| {
+        let mut socket = common::accept(stream);
+        common::read(&mut socket).unwrap();
+        // Dropped without a close handshake.
+    });
+    let client = common::client(&url).blocking_connect().unwrap();
+    let cause = match client.blocking_remove_slot("Slot1") {
+        Err(Error::Connection(cause)) => cause,
+        other => panic!("unexpected result: {other:?}"),
+    };
+    assert!(matches!(*cause, Error::WebSocket(_)), "{cause:?}");
+    assert!(Arc::ptr_eq(&cause, &client.last_error().unwrap()));
+    match client.blocking_remove_slot("Slot1") {
+        Err(Error::Connection(e)) => assert!(Arc::ptr_eq(&e, &cause)),
+        other => panic!("unexpected result: {other:?}"),
+    }
+    server.join().unwrap();
+}
+
+#[test]
+fn close_after_reconnecting_reports_closed() {
+    let (url, server) = common::serve(2, |index, stream| {
+        let mut socket = common::accept(stream);
+        if index == 1 {
+            common::answer_all(&mut socket);
+        }
+    });
+    let client = common::client(&url).reconnect(common::fast_reconnect()).blocking_connect().unwrap();
+    // Calls sent before the disconnect was noticed fail, the next one is held until reconnected.
+    let mut attempts = 0;
+    while client.blocking_remove_slot("Slot1").is_err() {
+        attempts += 1;
+        assert!(attempts < 10, "never reconnected");
+    }
+    assert_eq!(client.connection_state(), ConnectionState::Connected);
+    assert!(client.last_error().is_none());
+
+    let handle = client.handle();
+    client.blocking_close().unwrap();
+    assert!(matches!(handle.blocking_remove_slot("Slot1"), Err(Error::Closed)));
+    assert!(handle.last_error().is_none());
+    server.join().unwrap();
+}
+
+#[tokio::test]
+async fn timed_out_calls_stop_counting_in_flight() {
+    let (url, server) = common::serve(1, |_, stream| {
+        let mut socket = common::accept(stream);
+        // Never answered.
+        common::read(&mut socket).unwrap();
+        common::answer_all(&mut socket);
+    });
+    let client = common::client(&url)
+        .rate_limit(RateLimit { max_in_flight: Some(1), ..Default::default() })
+        .connect()
+        .await
+        .unwrap();
+    let result = client.call_with_timeout(common::remove_slot("Slot1"), None, Duration::from_millis(50)).await;
+    assert!(matches!(result, Err(Error::Timeout)), "{result:?}");
+    // Held back for good if the timed out call were still waiting for its response.
+    let next = tokio::time::timeout(Duration::from_secs(5), client.remove_slot("Slot2")).await;
+    assert!(matches!(next, Ok(Ok(_))), "{next:?}");
+
+    client.close().await.unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn timeouts_too_long_to_represent_never_expire() {
+    let (url, server) = common::serve(1, |_, stream| common::answer_all(&mut common::accept(stream)));
+    let client = common::client(&url).timeout(Duration::MAX).blocking_connect().unwrap();
+    client.blocking_remove_slot("Slot1").unwrap();
+    client.blocking_call_with_timeout(common::remove_slot("Slot2"), None, Duration::MAX).unwrap();
+    client.blocking_close().unwrap();
+    server.join().unwrap();
+}
+
+#[tokio::test]
+async fn graceful_close_waits_for_pending_responses() {
+    let (sent_tx, sent_rx) = tokio::sync::oneshot::channel();
+    let mut sent_tx = Some(sent_tx);
+    let (url, server) = common::serve(1, move |_, stream| {
+        let mut socket = common::accept(stream);
+        let first = common::read(&mut socket).unwrap();
+        common::read(&mut socket).unwrap();
+        sent_tx.take().unwrap().send(()).unwrap();
+        // Answered once the client is already closing, the second one never is.
+        std::thread::sleep(Duration::from_millis(200));
+        common::send(&mut socket, common::ok(&first));
+        while common::read(&mut socket).is_some() {}
+    });
+    let client = common::client(&url)
+        .rate_limit(RateLimit { max_in_flight: Some(2), ..Default::default() })
+        .connect()
+        .await
+        .unwrap();
+    let handle = client.handle();
+    let calls = tokio::spawn(async move {
+        tokio::join!(handle.remove_slot("Slot1"), handle.remove_slot("Slot2"), handle.remove_slot("Slot3"))
+    });
+    sent_rx.await.unwrap();
+
+    // The unanswered call and the one still queued behind `max_in_flight`.
+    assert_eq!(client.close_graceful(Duration::from_secs(1)).await.unwrap(), 2);
+    let (first, second, third) = calls.await.unwrap();
+    assert!(first.is_ok(), "{first:?}");
+    assert!(matches!(second, Err(Error::Closed)), "{second:?}");
+    assert!(matches!(third, Err(Error::Closed)), "{third:?}");
+    server.join().unwrap();
+}
+
+#[test]
+fn graceful_close_without_pending_requests() {
+    let (url, server) = common::serve(2, |_, stream| common::answer_all(&mut common::accept(stream)));
+    let client = common::client(&url).blocking_connect().unwrap();
+    client.blocking_remove_slot("Slot1").unwrap();
+    assert_eq!(client.blocking_close_graceful(Duration::MAX).unwrap(), 0);
+
+    let client = common::client(&url).blocking_connect().unwrap();
+    assert_eq!(client.blocking_close_graceful(Duration::ZERO).unwrap(), 0);
+    server.join().unwrap();
+}
+
+#[tokio::test]
+async fn undecodable_response_fails_its_call() {
+    let (url, server) = common::serve(1, |_, stream| {
+        let mut socket = common::accept(stream);
+        let msg = common::read(&mut socket).unwrap();
+        let text = format!(r#"{{"$type":"futureData","sourceMessageId":"{}","success":true}}"#, msg.message_id());
+        socket.send(tungstenite::Message::text(text)).unwrap();
+        common::answer_all(&mut socket);
+    });
+    let client = common::client(&url).connect().await.unwrap();
+    match client.remove_slot("Slot1").await {
+        Err(Error::Decode { text, .. }) => assert!(text.contains("futureData"), "{text}"),
+        other => panic!("unexpected result: {other:?}"),
+    }
+    client.remove_slot("Slot2").await.unwrap();
+    client.close().await.unwrap();
+    server.join().unwrap();
+}
+
+#[tokio::test]
+async fn unattributable_frames_are_diagnosed() {
+    let (url, server) = common::serve(1, |_, stream| {
+        let mut socket = common::accept(stream);
+        let msg = common::read(&mut socket).unwrap();
+        socket.send(tungstenite::Message::text("not json")).unwrap();
+        socket.send(tungstenite::Message::binary(vec![1, 2, 3])).unwrap();
+        common::send(&mut socket, common::ok(&msg));
+        common::answer_all(&mut socket);
+    });
+    let client = common::client(&url).connect().await.unwrap();
+    let mut diagnostics = client.diagnostics();
+    client.remove_slot("Slot1").await.unwrap();
+    match diagnostics.next().await {
+        Some(Ok(Diagnostic::UndecodableFrame { text, .. })) => assert_eq!(text, "not json"),
+        other => panic!("unexpected diagnostic: {other:?}"),
+    }
+    match diagnostics.next().await {
+        Some(Ok(Diagnostic::UnexpectedBinary(data))) => assert_eq!(data, [1, 2, 3].as_slice()),
+        other => panic!("unexpected diagnostic: {other:?}"),
+    }
+    assert_eq!(client.connection_state(), ConnectionState::Connected);
+    client.remove_slot("Slot2").await.unwrap();
+    client.close().await.unwrap();
+    server.join().unwrap();
+}
+
+#[tokio::test]
+async fn message_ids_are_generated_and_checked() {
+    let (received_tx, received_rx) = tokio::sync::oneshot::channel();
+    let (rejected_tx, rejected_rx) = std::sync::mpsc::channel();
+    let mut received_tx = Some(received_tx);
+    let (url, server) = common::serve(1, move |_, stream| {
+        let mut socket = common::accept(stream);
+        let generated = common::read(&mut socket).unwrap();
+        assert_eq!(generated.message_id(), "tool-1");
+        common::send(&mut socket, common::ok(&generated));
+
+        // Held until the client has tried to reuse its id.
+        let explicit = common::read(&mut socket).unwrap();
+        assert_eq!(explicit.message_id(), "explicit");
+        received_tx.take().unwrap().send(()).unwrap();
+        rejected_rx.recv().unwrap();
+        common::send(&mut socket, common::ok(&explicit));
+
+        let next = common::read(&mut socket).unwrap();
+        assert_eq!(next.message_id(), "tool-2");
+        common::send(&mut socket, common::ok(&next));
+        common::answer_all(&mut socket);
+    });
+    let client = common::client(&url).message_ids(SequentialIds::new("tool-")).connect().await.unwrap();
+    client.remove_slot("Slot1").await.unwrap();
+
+    let explicit = || -> Message { RemoveSlot { message_id: "explicit".to_string(), slot_id: "Slot2".to_string() }.into() };
+    let handle = client.handle();
+    let first = tokio::spawn(async move { handle.call(explicit(), None).await });
+    received_rx.await.unwrap();
+    match client.call(explicit(), None).await {
+        Err(Error::DuplicateMessageId(msg_id)) => assert_eq!(msg_id, "explicit"),
+        other => panic!("unexpected result: {other:?}"),
+    }
+    rejected_tx.send(()).unwrap();
+    assert_eq!(first.await.unwrap().unwrap().message_id(), "explicit");
+
+    client.remove_slot("Slot3").await.unwrap();
+    client.close().await.unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn failed_requests_return_the_server_error() {
+    let (url, server) = common::serve(1, |_, stream| {
+        let mut socket = common::accept(stream);
+        while let Some(msg) = common::read(&mut socket) {
+            let response = match msg {
+                // Answered with the wrong type of response.
+                Message::GetSlot(_) => common::ok(&msg),
+                _ => Response::Response(ResponseData {
+                    source_message_id: msg.message_id().to_string(),
+                    success: false,
+                    error_info: Some("Slot not found".to_string()),
+                }),
+            };
+            common::send(&mut socket, response);
+        }
+    });
+    let client = common::client(&url).blocking_connect().unwrap();
+    let error = match client.blocking_remove_slot("Slot1") {
+        Err(Error::Server(error)) => error,
+        other => panic!("unexpected result: {other:?}"),
+    };
+    assert_eq!(error.message_kind, Some("RemoveSlot"));
+    assert_eq!(error.error_info.as_deref(), Some("Slot not found"));
+    assert_eq!(error.to_string(), format!("RemoveSlot {} failed: Slot not found", error.source_message_id));
+
+    // The untyped call only fails if asked to.
+    let response = client.blocking_call(common::remove_slot("Slot1"), None).unwrap();
+    assert!(!response.success());
+    let error = response.into_result().unwrap_err();
+    assert_eq!(error.message_kind, None);
+    assert_eq!(error.to_string(), format!("{} failed: Slot not found", error.source_message_id));
+    assert_eq!(error.clone().with_message_kind("RemoveSlot"), ServerError { message_kind: Some("RemoveSlot"), ..error });
+
+    match client.blocking_get_slot("Slot1", 0, false) {
+        Err(Error::UnexpectedResponse(response)) => assert!(matches!(*response, Response::Response(_))),
+        other => panic!("unexpected result: {other:?}"),
+    }
+    client.blocking_close().unwrap();
+    server.join().unwrap();
+}
+
+#[tokio::test]
+async fn unanswered_session_request_only_delays_connecting() {
+    let (url, server) = common::serve(1, |_, stream| {
+        let mut socket = common::accept(stream);
+        while let Some(msg) = common::read(&mut socket) {
+            if !matches!(msg, Message::RequestSessionData(_)) {
+                common::send(&mut socket, common::ok(&msg));
+            }
+        }
+    });
+    let start = std::time::Instant::now();
+    let client = Client::builder(&url).unwrap().connect().await.unwrap();
+    client.remove_slot("Slot1").await.unwrap();
+    assert!(start.elapsed() >= ClientBuilder::DEFAULT_SESSION_TIMEOUT);
+    assert!(start.elapsed() < ClientBuilder::DEFAULT_SESSION_TIMEOUT * 3, "{:?}", start.elapsed());
+    assert!(client.session_info().is_none());
+    client.close().await.unwrap();
+    server.join().unwrap();
+}
@@ -0,0 +1,78 @@
+#![allow(dead_code)]
+
+use std::net::{TcpListener, TcpStream};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use resoxide_link::client::{Client, ClientBuilder, ReconnectPolicy};
+use resoxide_link::messages::{Message, RemoveSlot};
+use resoxide_link::responses::{Response, ResponseData};
+
+pub type Socket = tungstenite::WebSocket<TcpStream>;
+
+/// Accepts `connections` connections one after another and hands each to `handler` along with its
+/// index. The handshake is left to the handler, so it can keep the client waiting.
+pub fn serve<F>(connections: usize, mut handler: F) -> (String, JoinHandle<()>)
+where
+    F: FnMut(usize, TcpStream) + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        for index in 0..connections {
+            let (stream, _) = listener.accept().unwrap();
+            handler(index, stream);
+        }
+    });
+    (url, server)
+}
+
+pub fn accept(stream: TcpStream) -> Socket {
+    tungstenite::accept(stream).unwrap()
+}
+
+/// Reads the next message, `None` once the client is gone.
+pub fn read(socket: &mut Socket) -> Option<Message> {
+    loop {
+        match socket.read() {
+            Ok(tungstenite::Message::Text(text)) => return Some(Message::deserialize(text.as_str()).unwrap()),
+            Ok(_) => continue,
+            Err(_) => return None,
+        }
+    }
+}
+
+pub fn send(socket: &mut Socket, response: Response) {
+    socket.send(tungstenite::Message::text(response.serialize().unwrap())).unwrap();
+}
+
+pub fn ok(msg: &Message) -> Response {
+    Response::Response(ResponseData {
+        source_message_id: msg.message_id().to_string(),
+        success: true,
+        error_info: None,
+    })
+}
+
+pub fn remove_slot(slot_id: &str) -> Message {
+    RemoveSlot { message_id: Default::default(), slot_id: slot_id.to_string() }.into()
+}
+
+/// Answers every message with `ok` until the client is gone.
+pub fn answer_all(socket: &mut Socket) {
+    while let Some(msg) = read(socket) {
+        send(socket, ok(&msg));
+    }
+}
+
+/// Doesn't ask for the session data, which the mock servers don't answer.
+pub fn client(url: &str) -> ClientBuilder {
+    Client::builder(url).unwrap().session_info(None)
+}
+
+pub fn fast_reconnect() -> ReconnectPolicy {
+    ReconnectPolicy {
+        initial_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(10),
+        ..Default::default()
+    }
+}
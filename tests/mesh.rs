@@ -0,0 +1,77 @@
+use resoxide_link::data_model::{Float2, Float3};
+use resoxide_link::mesh::{BoneWeight, MeshData, MeshError, Topology};
+use resoxide_link::messages::Message;
+
+fn triangle() -> MeshData {
+    MeshData::new(vec![Float3::new(0.0, 0.0, 0.0), Float3::new(1.0, 0.0, 0.0), Float3::new(0.0, 1.0, 0.0)])
+}
+
+fn le(values: &[f32]) -> Vec<u8> {
+    values.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+#[test]
+fn payload_matches_header() {
+    let mesh = triangle()
+        .with_normals(vec![Float3::new(0.0, 0.0, 1.0); 3])
+        .with_uv_channel(vec![Float2::new(0.0, 0.0), Float2::new(1.0, 0.0), Float2::new(0.0, 1.0)])
+        .with_triangles(vec![0, 1, 2])
+        .with_points(vec![1]);
+    let (msg, data) = mesh.encode().expect("encode mesh");
+    assert_eq!(msg.vertex_count, 3);
+    assert!(msg.has_normals && !msg.has_tangents && !msg.has_colors);
+    assert_eq!(msg.uv_channel_count, 1);
+    assert_eq!(msg.submeshes.len(), 2);
+    assert_eq!(msg.submeshes[1].topology, Topology::Points.name());
+    assert_eq!(Message::from(msg).binary_len(), Some(data.len()));
+}
+
+#[test]
+fn byte_layout() {
+    let mesh = MeshData::new(vec![Float3::new(1.0, 2.0, 3.0)])
+        .with_normals(vec![Float3::new(0.0, 1.0, 0.0)])
+        .with_uv_channel(vec![Float2::new(0.25, 0.75)])
+        .with_bone_weights(vec![vec![
+            BoneWeight { bone_index: 2, weight: 0.6 },
+            BoneWeight { bone_index: 5, weight: 0.4 },
+        ]])
+        .with_points(vec![0]);
+    let (msg, data) = mesh.encode().expect("encode mesh");
+    assert_eq!(msg.bone_weight_count, 2);
+
+    let mut expected = le(&[1.0, 2.0, 3.0]);
+    expected.extend(le(&[0.0, 1.0, 0.0]));
+    expected.extend(le(&[0.25, 0.75]));
+    expected.extend(2i32.to_le_bytes());
+    expected.extend(0.6f32.to_le_bytes());
+    expected.extend(5i32.to_le_bytes());
+    expected.extend(0.4f32.to_le_bytes());
+    expected.extend(0i32.to_le_bytes());
+    assert_eq!(data, expected);
+    assert_eq!(msg.binary_len(), data.len());
+}
+
+#[test]
+fn rejects_invalid_meshes() {
+    assert_eq!(
+        triangle().with_normals(vec![Float3::new(0.0, 0.0, 1.0); 2]).encode().unwrap_err(),
+        MeshError::AttributeLength { attribute: "normals", expected: 3, actual: 2 },
+    );
+    assert_eq!(
+        triangle().with_uv_channel(vec![]).encode().unwrap_err(),
+        MeshError::AttributeLength { attribute: "uv_channels", expected: 3, actual: 0 },
+    );
+    let weight = BoneWeight { bone_index: 0, weight: 1.0 };
+    assert_eq!(
+        triangle().with_bone_weights(vec![vec![weight], vec![weight, weight], vec![weight]]).encode().unwrap_err(),
+        MeshError::BoneWeightCount { vertex: 1, expected: 1, actual: 2 },
+    );
+    assert_eq!(
+        triangle().with_points(vec![0]).with_triangles(vec![0, 1, 3]).encode().unwrap_err(),
+        MeshError::IndexOutOfRange { submesh: 1, index: 3 },
+    );
+    assert_eq!(
+        triangle().with_triangles(vec![0, 1]).encode().unwrap_err(),
+        MeshError::IncompletePrimitive { submesh: 0, topology: Topology::Triangles, index_count: 2 },
+    );
+}
@@ -0,0 +1,139 @@
+#![cfg(feature = "client")]
+
+mod common;
+
+use std::time::Duration;
+use tokio_stream::StreamExt;
+use resoxide_link::client::{ConnectionState, Error, PendingPolicy, ReconnectPolicy};
+use resoxide_link::data_model::Slot;
+use resoxide_link::messages::{Message, RemoveSlot};
+use resoxide_link::responses::{Response, SlotData};
+
+/// How long the mock servers keep the client waiting before accepting the new connection.
+const RECONNECT_DELAY: Duration = Duration::from_millis(200);
+
+fn slot_data(msg: &Message) -> Response {
+    Response::SlotData(SlotData {
+        source_message_id: msg.message_id().to_string(),
+        success: true,
+        ..Default::default()
+    })
+}
+
+async fn wait_for(states: &mut tokio_stream::wrappers::WatchStream<ConnectionState>, state: ConnectionState) {
+    let wait = async {
+        while states.next().await.expect("connection task ended") != state {}
+    };
+    tokio::time::timeout(Duration::from_secs(5), wait).await.expect("state not reached");
+}
+
+#[tokio::test]
+async fn pending_calls_fail_when_the_connection_is_lost() {
+    let (url, server) = common::serve(2, |index, stream| {
+        if index == 1 {
+            std::thread::sleep(RECONNECT_DELAY);
+        }
+        let mut socket = common::accept(stream);
+        match index {
+            // Dropped without answering.
+            0 => drop(common::read(&mut socket).unwrap()),
+            _ => common::answer_all(&mut socket),
+        }
+    });
+    let client = common::client(&url).reconnect(common::fast_reconnect()).connect().await.unwrap();
+    let mut states = client.watch_connection_state();
+    assert_eq!(states.next().await, Some(ConnectionState::Connected));
+
+    let result = client.get_slot(Slot::ROOT_SLOT_ID, 0, false).await;
+    assert!(matches!(result, Err(Error::Reconnected)), "{result:?}");
+    assert_eq!(states.next().await, Some(ConnectionState::Connecting));
+    assert_eq!(states.next().await, Some(ConnectionState::Connected));
+    client.remove_slot("Slot1").await.unwrap();
+
+    client.close().await.unwrap();
+    assert_eq!(states.next().await, Some(ConnectionState::Disconnected));
+    server.join().unwrap();
+}
+
+#[tokio::test]
+async fn idempotent_calls_are_resent() {
+    let mut sent_id = String::new();
+    let (url, server) = common::serve(2, move |index, stream| {
+        let mut socket = common::accept(stream);
+        if index == 0 {
+            let msg = common::read(&mut socket).unwrap();
+            sent_id = msg.message_id().to_string();
+            common::read(&mut socket).unwrap();
+            return;
+        }
+        // Only the idempotent call comes back, with the id it was sent with.
+        let msg = common::read(&mut socket).unwrap();
+        assert!(matches!(msg, Message::GetSlot(_)), "{msg:?}");
+        assert_eq!(msg.message_id(), sent_id);
+        common::send(&mut socket, slot_data(&msg));
+        common::answer_all(&mut socket);
+    });
+    let policy = ReconnectPolicy { pending: PendingPolicy::ResendIdempotent, ..common::fast_reconnect() };
+    let client = common::client(&url).reconnect(policy).timeout(Duration::from_secs(5)).connect().await.unwrap();
+
+    let (slot, removed) = tokio::join!(
+        client.get_slot(Slot::ROOT_SLOT_ID, 0, false),
+        client.remove_slot("Slot1"),
+    );
+    assert!(slot.is_ok(), "{slot:?}");
+    assert!(matches!(removed, Err(Error::Reconnected)), "{removed:?}");
+
+    client.close().await.unwrap();
+    server.join().unwrap();
+}
+
+#[tokio::test]
+async fn calls_wait_for_the_new_connection() {
+    let (url, server) = common::serve(2, |index, stream| {
+        if index == 1 {
+            std::thread::sleep(RECONNECT_DELAY);
+        }
+        let mut socket = common::accept(stream);
+        if index == 0 {
+            return;
+        }
+        let mut removed = vec![];
+        while let Some(msg) = common::read(&mut socket) {
+            if let Message::RemoveSlot(RemoveSlot { slot_id, .. }) = &msg {
+                removed.push(slot_id.clone());
+            }
+            common::send(&mut socket, common::ok(&msg));
+        }
+        assert_eq!(removed, ["Slot1"]);
+    });
+    let client = common::client(&url).reconnect(common::fast_reconnect()).connect().await.unwrap();
+    let mut states = client.watch_connection_state();
+    wait_for(&mut states, ConnectionState::Connecting).await;
+
+    // A caller that gives up while its call is held back isn't sent, and doesn't block the next one.
+    let result = client.call_with_timeout(common::remove_slot("Gone"), None, Duration::from_millis(20)).await;
+    assert!(matches!(result, Err(Error::Timeout)), "{result:?}");
+    assert_eq!(client.connection_state(), ConnectionState::Connecting);
+    client.remove_slot("Slot1").await.unwrap();
+    assert_eq!(client.connection_state(), ConnectionState::Connected);
+
+    client.close().await.unwrap();
+    server.join().unwrap();
+}
+
+#[tokio::test]
+async fn reconnecting_stops_once_every_handle_is_dropped() {
+    // The listener is gone after the first connection, so every attempt to reconnect fails.
+    let (url, server) = common::serve(1, |_, stream| drop(common::accept(stream)));
+    let client = common::client(&url).reconnect(common::fast_reconnect()).connect().await.unwrap();
+    let handle = client.handle();
+    let mut states = client.watch_connection_state();
+    wait_for(&mut states, ConnectionState::Connecting).await;
+
+    drop(client);
+    drop(handle);
+    wait_for(&mut states, ConnectionState::Disconnected).await;
+    let ended = tokio::time::timeout(Duration::from_secs(5), states.next()).await;
+    assert_eq!(ended, Ok(None));
+    server.join().unwrap();
+}
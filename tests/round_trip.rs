@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use resoxide_link::audio::encode_samples;
+use resoxide_link::data_model::{Component, ComponentDefinition, EnumDefinition, EnumValue, Float2, Float3, Member, MemberDefinition, Slot};
+use resoxide_link::mesh::MeshData;
+use resoxide_link::messages::*;
+use resoxide_link::responses::*;
+
+fn round_trip_message(msg: Message) {
+    let json = msg.serialize().expect("serialize");
+    let decoded = Message::deserialize(&json).expect("deserialize");
+    assert_eq!(decoded.kind(), msg.kind());
+    assert_eq!(decoded.message_id(), msg.message_id());
+    assert_eq!(decoded.serialize().expect("serialize again"), json);
+}
+
+fn round_trip_response(resp: Response) {
+    let json = resp.serialize().expect("serialize");
+    let decoded = Response::deserialize(&json).expect("deserialize");
+    assert_eq!(std::mem::discriminant(&decoded), std::mem::discriminant(&resp));
+    assert_eq!(decoded.message_id(), resp.message_id());
+    assert_eq!(decoded.success(), resp.success());
+    assert_eq!(decoded.error_info(), resp.error_info());
+    assert_eq!(decoded.serialize().expect("serialize again"), json);
+}
+
+fn members() -> HashMap<String, Member> {
+    HashMap::from([("Text".to_string(), Member::from("Hello"))])
+}
+
+#[test]
+fn slot_messages() {
+    round_trip_message(Message::GetSlot(GetSlot {
+        message_id: "1".to_string(),
+        slot_id: Slot::ROOT_SLOT_ID.to_string(),
+        depth: 2,
+        include_component_data: true,
+    }));
+    let slot = Slot::new(Slot::ROOT_SLOT_ID, "Slot".to_string()).with_position(Float3::new(1.0, 2.0, 3.0));
+    round_trip_message(Message::AddSlot(AddSlot { message_id: "2".to_string(), data: AddSlotData::from(slot) }));
+    round_trip_message(Message::UpdateSlot(UpdateSlot {
+        message_id: "3".to_string(),
+        data: UpdateSlotData { id: "Slot1".to_string(), ..Default::default() },
+    }));
+    round_trip_message(Message::RemoveSlot(RemoveSlot { message_id: "4".to_string(), slot_id: "Slot1".to_string() }));
+}
+
+#[test]
+fn component_messages() {
+    round_trip_message(Message::GetComponent(GetComponent { message_id: "1".to_string(), component_id: "Comp1".to_string() }));
+    round_trip_message(Message::AddComponent(AddComponent {
+        message_id: "2".to_string(),
+        container_slot_id: "Slot1".to_string(),
+        data: AddComponentData { id: None, component_type: "[FrooxEngine]FrooxEngine.TextRenderer".to_string(), members: members() },
+    }));
+    round_trip_message(Message::UpdateComponent(UpdateComponent {
+        message_id: "3".to_string(),
+        data: UpdateComponentData { id: "Comp1".to_string(), members: members() },
+    }));
+    round_trip_message(Message::RemoveComponent(RemoveComponent { message_id: "4".to_string(), component_id: "Comp1".to_string() }));
+}
+
+#[test]
+fn import_messages() {
+    round_trip_message(Message::ImportTexture2DFile(ImportTexture2DFile { message_id: "1".to_string(), file_path: "a.png".to_string() }));
+    round_trip_message(Message::ImportTexture2DRawData(ImportTexture2DRawData {
+        message_id: "2".to_string(),
+        width: 2,
+        height: 3,
+        color_profile: "sRGB".to_string(),
+    }));
+    round_trip_message(Message::ImportTexture2DRawDataHDR(ImportTexture2DRawDataHDR { message_id: "3".to_string(), width: 2, height: 3 }));
+    round_trip_message(Message::ImportMeshFile(ImportMeshFile { message_id: "4".to_string(), file_path: "a.glb".to_string() }));
+    let (msg, _) = MeshData::new(vec![Float3::new(0.0, 0.0, 0.0), Float3::new(1.0, 0.0, 0.0), Float3::new(0.0, 1.0, 0.0)])
+        .with_uv_channel(vec![Float2::new(0.0, 0.0), Float2::new(1.0, 0.0), Float2::new(0.0, 1.0)])
+        .with_triangles(vec![0, 1, 2])
+        .with_points(vec![0])
+        .encode()
+        .expect("encode mesh");
+    round_trip_message(Message::ImportMeshRawData(ImportMeshRawData { message_id: "5".to_string(), ..msg }));
+    round_trip_message(Message::ImportAudioClipFile(ImportAudioClipFile { message_id: "6".to_string(), file_path: "a.wav".to_string() }));
+    round_trip_message(Message::ImportAudioClipRawData(ImportAudioClipRawData {
+        message_id: "7".to_string(),
+        sample_count: 4,
+        sample_rate: 48000,
+        channel_count: 2,
+    }));
+}
+
+#[test]
+fn definition_messages() {
+    round_trip_message(Message::RequestSessionData(RequestSessionData { message_id: "1".to_string() }));
+    round_trip_message(Message::GetComponentDefinition(GetComponentDefinition {
+        message_id: "2".to_string(),
+        component_type: "[FrooxEngine]FrooxEngine.TextRenderer".to_string(),
+    }));
+    round_trip_message(Message::GetEnumDefinition(GetEnumDefinition {
+        message_id: "3".to_string(),
+        enum_type: "[FrooxEngine]FrooxEngine.TextHorizontalAlignment".to_string(),
+    }));
+}
+
+#[test]
+fn responses() {
+    round_trip_response(Response::Response(ResponseData::default()));
+    round_trip_response(Response::Response(ResponseData {
+        source_message_id: "0".to_string(),
+        success: true,
+        error_info: None,
+    }));
+    round_trip_response(Response::SlotData(SlotData {
+        source_message_id: "1".to_string(),
+        success: true,
+        error_info: None,
+        depth: 0,
+        data: Slot::new(Slot::ROOT_SLOT_ID, "Slot".to_string()),
+    }));
+    round_trip_response(Response::ComponentData(ComponentData {
+        source_message_id: "2".to_string(),
+        success: false,
+        error_info: Some("Component not found".to_string()),
+        data: Component::new("[FrooxEngine]FrooxEngine.TextRenderer".to_string()).with_member("Text".to_string(), Member::from("Hello")),
+    }));
+    round_trip_response(Response::SessionData(SessionData {
+        source_message_id: "3".to_string(),
+        success: true,
+        world_name: Some("World".to_string()),
+        local_user_id: Some("U-1".to_string()),
+        ..Default::default()
+    }));
+    round_trip_response(Response::ComponentDefinitionData(ComponentDefinitionData {
+        source_message_id: "4".to_string(),
+        success: true,
+        error_info: None,
+        definition: ComponentDefinition {
+            component_type: "[FrooxEngine]FrooxEngine.TextRenderer".to_string(),
+            generic_arguments: vec![],
+            members: HashMap::from([("Text".to_string(), MemberDefinition { member_type: "string".to_string(), value_type: None })]),
+        },
+    }));
+    round_trip_response(Response::EnumDefinitionData(EnumDefinitionData {
+        source_message_id: "5".to_string(),
+        success: true,
+        error_info: None,
+        definition: EnumDefinition {
+            enum_type: "[FrooxEngine]FrooxEngine.TextHorizontalAlignment".to_string(),
+            underlying_type: "int".to_string(),
+            is_flags: false,
+            values: vec![EnumValue { name: "Left".to_string(), value: 0 }, EnumValue { name: "Center".to_string(), value: 1 }],
+        },
+    }));
+    round_trip_response(Response::AssetData(AssetData {
+        source_message_id: "6".to_string(),
+        success: true,
+        error_info: None,
+        asset_url: Some("resdb:///asset".to_string()),
+    }));
+}
+
+#[test]
+fn frames_pair_binary_payloads() {
+    let mut frames = MessageFrames::default();
+    let text = Message::GetSlot(GetSlot { message_id: "1".to_string(), ..Default::default() }).serialize().unwrap();
+    assert!(matches!(frames.text(&text), Ok(Some(Message::GetSlot(_)))));
+
+    let samples = [0.0, 0.5, -0.5, 1.0];
+    let (msg, data) = encode_samples(&samples, 48000, 2).expect("encode samples");
+    let text = Message::from(ImportAudioClipRawData { message_id: "2".to_string(), ..msg }).serialize().unwrap();
+    assert!(matches!(frames.text(&text), Ok(None)));
+    assert!(frames.is_awaiting_binary());
+    let (msg, data) = frames.binary(data).expect("binary");
+    assert_eq!(msg.message_id(), "2");
+    assert_eq!(data.len(), samples.len() * 4);
+    assert!(!frames.is_awaiting_binary());
+}
+
+#[test]
+fn frames_reject_mismatched_binary() {
+    let mut frames = MessageFrames::default();
+    assert!(matches!(frames.binary([0u8; 4]), Err(FrameError::UnexpectedBinary)));
+
+    let texture = Message::from(ImportTexture2DRawData { message_id: "1".to_string(), width: 1, height: 1, color_profile: "sRGB".to_string() });
+    let text = texture.serialize().unwrap();
+    assert!(matches!(frames.text(&text), Ok(None)));
+    match frames.binary([0u8; 3]) {
+        Err(FrameError::PayloadLength { expected: 4, actual: 3, .. }) => {}
+        other => panic!("unexpected result: {other:?}"),
+    }
+
+    let next = Message::from(RemoveSlot { message_id: "2".to_string(), slot_id: "Slot1".to_string() }).serialize().unwrap();
+    assert!(matches!(frames.text(&text), Ok(None)));
+    match frames.text(&next) {
+        Err(FrameError::MissingBinary { orphan, next: Some(next) }) => {
+            assert_eq!(orphan.message_id(), "1");
+            assert!(matches!(*next, Message::RemoveSlot(_)));
+        }
+        other => panic!("unexpected result: {other:?}"),
+    }
+    assert!(!frames.is_awaiting_binary());
+
+    // A message that waits for its own binary frame replaces the orphan.
+    assert!(matches!(frames.text(&text), Ok(None)));
+    assert!(matches!(frames.text(&text), Err(FrameError::MissingBinary { next: None, .. })));
+    assert!(frames.is_awaiting_binary());
+    assert!(matches!(frames.text("not json"), Err(FrameError::Json(_))));
+    assert_eq!(frames.binary([0u8; 4]).expect("binary").0.message_id(), "1");
+}